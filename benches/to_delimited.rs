@@ -6,7 +6,10 @@ use std::fs;
 use std::path::Path;
 
 use criterion::Criterion;
-use ga_v4_flattener::to_delimited;
+use ga_v4_flattener::{
+    to_delimited, write_delimited, write_delimited_from_reader, DelimitedOptions,
+};
+use std::io;
 
 fn to_delimited_large_report_benchmark(c: &mut Criterion) {
     let data: String = fs::read_to_string(
@@ -30,9 +33,39 @@ fn to_delimited_multi_report_benchmark(c: &mut Criterion) {
     });
 }
 
+fn write_delimited_large_report_benchmark(c: &mut Criterion) {
+    let data: String = fs::read_to_string(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/large_report.json"),
+    )
+    .unwrap();
+
+    c.bench_function("write_delimited_large_report", move |b| {
+        b.iter(|| write_delimited(&data, &mut io::sink(), &DelimitedOptions::default()))
+    });
+}
+
+fn write_delimited_from_reader_large_report_benchmark(c: &mut Criterion) {
+    let data: String = fs::read_to_string(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/large_report.json"),
+    )
+    .unwrap();
+
+    c.bench_function("write_delimited_from_reader_large_report", move |b| {
+        b.iter(|| {
+            write_delimited_from_reader(
+                data.as_bytes(),
+                &mut io::sink(),
+                &DelimitedOptions::default(),
+            )
+        })
+    });
+}
+
 criterion_group!(
     benches,
     to_delimited_large_report_benchmark,
-    to_delimited_multi_report_benchmark
+    to_delimited_multi_report_benchmark,
+    write_delimited_large_report_benchmark,
+    write_delimited_from_reader_large_report_benchmark
 );
 criterion_main!(benches);
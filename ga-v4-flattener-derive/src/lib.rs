@@ -0,0 +1,217 @@
+//! `#[derive(GaReportRow)]` for `ga-v4-flattener`, usually used through its `derive` feature.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::ext::IdentExt;
+use syn::{parse_macro_input, Data, DeriveInput, Field, Fields, LitStr};
+
+/// Implements `GaReportRow` for a struct whose every field declares the column it's filled
+/// from with `#[ga(dimension = "ga:country")]` or `#[ga(metric = "ga:sessions")]`.
+///
+/// Dimensions and metrics are requested in field order. Fields are filled by their Rust
+/// names, so they shouldn't be renamed with `#[serde(rename = "...")]`; the struct also needs
+/// `#[derive(Deserialize)]`.
+#[proc_macro_derive(GaReportRow, attributes(ga))]
+pub fn derive_ga_report_row(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+enum Declared {
+    Dimension(LitStr),
+    Metric(LitStr),
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &data.fields,
+                    "GaReportRow needs a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "GaReportRow can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut dimensions: Vec<(LitStr, String)> = Vec::new();
+    let mut metrics: Vec<(LitStr, String)> = Vec::new();
+    for field in fields {
+        let name = field.ident.as_ref().unwrap().unraw().to_string();
+        match declared_column(field)? {
+            Declared::Dimension(column) => dimensions.push((column, name)),
+            Declared::Metric(column) => metrics.push((column, name)),
+        }
+    }
+
+    let declared: Vec<&LitStr> = dimensions
+        .iter()
+        .chain(metrics.iter())
+        .map(|(column, _)| column)
+        .collect();
+    for (index, column) in declared.iter().enumerate() {
+        if declared[..index]
+            .iter()
+            .any(|earlier| earlier.value() == column.value())
+        {
+            return Err(syn::Error::new(
+                column.span(),
+                format!("\"{}\" is declared by more than one field", column.value()),
+            ));
+        }
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let dimension_columns = dimensions.iter().map(|(column, _)| column);
+    let metric_columns = metrics.iter().map(|(column, _)| column);
+    let field_names = dimensions
+        .iter()
+        .chain(metrics.iter())
+        .map(|(_, name)| name);
+
+    Ok(quote! {
+        impl #impl_generics ::ga_v4_flattener::GaReportRow for #ident #type_generics #where_clause {
+            const DIMENSIONS: &'static [&'static str] = &[#(#dimension_columns),*];
+            const METRICS: &'static [&'static str] = &[#(#metric_columns),*];
+            const FIELDS: &'static [&'static str] = &[#(#field_names),*];
+        }
+    })
+}
+
+// the single `dimension` or `metric` a field's `#[ga(...)]` attributes declare
+fn declared_column(field: &Field) -> syn::Result<Declared> {
+    let mut declared: Option<Declared> = None;
+
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("ga")) {
+        attr.parse_nested_meta(|meta| {
+            let column = if meta.path.is_ident("dimension") {
+                Declared::Dimension(meta.value()?.parse()?)
+            } else if meta.path.is_ident("metric") {
+                Declared::Metric(meta.value()?.parse()?)
+            } else {
+                return Err(meta.error("expected `dimension` or `metric`"));
+            };
+
+            let name = match &column {
+                Declared::Dimension(name) | Declared::Metric(name) => name,
+            };
+            if name.value().is_empty() {
+                return Err(syn::Error::new(name.span(), "column names can't be empty"));
+            }
+            if declared.is_some() {
+                return Err(meta.error("a field can only declare one dimension or metric"));
+            }
+
+            declared = Some(column);
+            Ok(())
+        })?;
+    }
+
+    declared.ok_or_else(|| {
+        syn::Error::new_spanned(
+            field,
+            "expected #[ga(dimension = \"...\")] or #[ga(metric = \"...\")] on every field",
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_str(input: &str) -> Result<String, String> {
+        let input: DeriveInput = syn::parse_str(input).unwrap();
+
+        expand(&input)
+            .map(|tokens| tokens.to_string())
+            .map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn lists_columns_in_field_order() {
+        let expanded = expand_str(
+            r#"
+            struct Row {
+                #[ga(metric = "ga:sessions")]
+                sessions: u32,
+                #[ga(dimension = "ga:country")]
+                country: String,
+                #[ga(metric = "ga:users")]
+                r#users: u32,
+                #[ga(dimension = "ga:city")]
+                city: String,
+            }
+            "#,
+        )
+        .unwrap();
+
+        let expected = quote! {
+            impl ::ga_v4_flattener::GaReportRow for Row {
+                const DIMENSIONS: &'static [&'static str] = &["ga:country", "ga:city"];
+                const METRICS: &'static [&'static str] = &["ga:sessions", "ga:users"];
+                const FIELDS: &'static [&'static str] = &["country", "city", "sessions", "users"];
+            }
+        };
+        assert_eq!(expanded, expected.to_string());
+    }
+
+    #[test]
+    fn rejects_undeclared_fields() {
+        assert_eq!(
+            expand_str("struct Row { #[ga(metric = \"ga:users\")] users: u32, country: String }"),
+            Err(
+                "expected #[ga(dimension = \"...\")] or #[ga(metric = \"...\")] on every field"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_declarations() {
+        assert_eq!(
+            expand_str("struct Row { #[ga(segment = \"x\")] segment: String }"),
+            Err("expected `dimension` or `metric`".to_string())
+        );
+        assert_eq!(
+            expand_str("struct Row { #[ga(dimension = \"\")] country: String }"),
+            Err("column names can't be empty".to_string())
+        );
+        assert_eq!(
+            expand_str(
+                "struct Row { #[ga(dimension = \"ga:city\", metric = \"ga:users\")] city: String }"
+            ),
+            Err("a field can only declare one dimension or metric".to_string())
+        );
+        assert_eq!(
+            expand_str(
+                "struct Row {
+                    #[ga(metric = \"ga:users\")] users: u32,
+                    #[ga(metric = \"ga:users\")] also_users: u32,
+                }"
+            ),
+            Err("\"ga:users\" is declared by more than one field".to_string())
+        );
+        assert_eq!(
+            expand_str("struct Row(String);"),
+            Err("GaReportRow needs a struct with named fields".to_string())
+        );
+        assert_eq!(
+            expand_str("enum Row { Country }"),
+            Err("GaReportRow can only be derived for structs".to_string())
+        );
+    }
+}
@@ -0,0 +1,101 @@
+#[macro_use]
+extern crate serde_derive;
+
+use ga_v4_flattener::types::ReportResponse;
+use ga_v4_flattener::{FlattenError, GaReportRow, HeaderKind, HeaderMismatch};
+use std::fs;
+use std::path::Path;
+
+fn read_response(name: &str) -> ReportResponse {
+    let data: String = fs::read_to_string(
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("..")
+            .join("test_reports")
+            .join(name),
+    )
+    .unwrap();
+
+    serde_json::from_str(data.as_str()).unwrap()
+}
+
+#[derive(Deserialize, GaReportRow, Debug, PartialEq)]
+struct CountryRow {
+    #[ga(dimension = "ga:country")]
+    country: String,
+    #[ga(metric = "ga:sessions")]
+    sessions: u32,
+    #[ga(metric = "ga:bounceRate")]
+    bounce_rate: f64,
+    #[ga(metric = "ga:transactionRevenue")]
+    revenue: f32,
+    #[ga(metric = "ga:pageviewsPerSession")]
+    pages_per_session: f64,
+    #[ga(metric = "ga:avgSessionDuration")]
+    r#duration: f64,
+}
+
+#[test]
+fn reads_rows_of_a_matching_report() {
+    let response = read_response("metric_types.json");
+
+    let rows = CountryRow::from_report(&response.reports[0], 0).unwrap();
+
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].country, "Canada");
+    assert_eq!(rows[0].sessions, 12);
+    assert_eq!(rows[0].revenue, 10.0);
+    assert_eq!(rows[0].duration, 3725.5);
+}
+
+#[test]
+fn declares_the_requested_columns() {
+    assert_eq!(CountryRow::DIMENSIONS, &["ga:country"]);
+    assert_eq!(
+        CountryRow::METRICS,
+        &[
+            "ga:sessions",
+            "ga:bounceRate",
+            "ga:transactionRevenue",
+            "ga:pageviewsPerSession",
+            "ga:avgSessionDuration",
+        ]
+    );
+    assert_eq!(
+        CountryRow::request_columns()["metrics"][2],
+        serde_json::json!({ "expression": "ga:transactionRevenue" })
+    );
+}
+
+#[derive(Deserialize, GaReportRow, Debug)]
+#[allow(dead_code)]
+struct DeviceRow {
+    #[ga(dimension = "ga:deviceCategory")]
+    device: String,
+    #[ga(metric = "ga:users")]
+    users: u32,
+}
+
+#[test]
+fn rejects_a_mismatched_report() {
+    let response = read_response("single_dimension_and_metric.json");
+
+    match DeviceRow::from_report(&response.reports[0], 0) {
+        Err(FlattenError::ColumnHeaderMismatch {
+            kind,
+            position,
+            mismatch,
+            ..
+        }) => {
+            assert_eq!(kind, HeaderKind::Metric);
+            assert_eq!(position, 0);
+            assert_eq!(
+                mismatch,
+                HeaderMismatch::Different {
+                    expected: "ga:users".to_string(),
+                    found: "ga:sessions".to_string(),
+                }
+            );
+        }
+        other => panic!("expected a column header mismatch, got {:?}", other),
+    }
+}
@@ -0,0 +1,253 @@
+use crate::error::FlattenError;
+use crate::types::{ColumnHeader, MetricHeaderEntry, MetricType};
+use serde_json::Number;
+use std::str::FromStr;
+
+/// Adds columns comparing every other date range's metrics to those of a base date range.
+///
+/// For each metric there is a `_delta` column with the difference from the base and a
+/// `_pct_change` column with that difference as a percentage of the base, which is empty
+/// when the base is zero. `PERCENT` metrics only get a delta, in percentage points.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Comparison {
+    /// The index of the date range to compare against, from 0
+    pub base_date_range: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ComparisonKind {
+    Delta,
+    PercentChange,
+}
+
+/// A single comparison column, between the values at `base` and `other` in a row's flat
+/// metric values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComparisonColumn {
+    pub name: String,
+    kind: ComparisonKind,
+    metric_type: MetricType,
+    base: usize,
+    other: usize,
+}
+
+impl Comparison {
+    // metric_headers are the wide layout's headers, named however the date ranges are
+    // named, and the comparison columns are named after those of the other date range
+    pub fn columns(
+        &self,
+        column_header: &ColumnHeader,
+        metric_headers: &[MetricHeaderEntry],
+        number_of_date_ranges: usize,
+        report_index: usize,
+    ) -> Result<Vec<ComparisonColumn>, FlattenError> {
+        if number_of_date_ranges == 0 {
+            return Ok(Vec::new());
+        }
+        if self.base_date_range >= number_of_date_ranges {
+            return Err(FlattenError::UnsupportedFeature(format!(
+                "report {} has {} date ranges, so date range {} can't be compared against",
+                report_index, number_of_date_ranges, self.base_date_range
+            )));
+        }
+
+        let metrics_per_date_range = column_header.metric_header.flat_header_entries().len();
+        let mut columns = Vec::new();
+
+        for date_range in (0..number_of_date_ranges).filter(|&i| i != self.base_date_range) {
+            for metric in 0..metrics_per_date_range {
+                let base = self.base_date_range * metrics_per_date_range + metric;
+                let other = date_range * metrics_per_date_range + metric;
+                let header = &metric_headers[other];
+
+                let mut kinds = vec![ComparisonKind::Delta];
+                if header.metric_type != MetricType::Percent {
+                    kinds.push(ComparisonKind::PercentChange);
+                }
+
+                for kind in kinds {
+                    let suffix = match kind {
+                        ComparisonKind::Delta => "delta",
+                        ComparisonKind::PercentChange => "pct_change",
+                    };
+                    columns.push(ComparisonColumn {
+                        name: format!("{}_{}", header.name, suffix),
+                        kind,
+                        metric_type: header.metric_type.clone(),
+                        base,
+                        other,
+                    });
+                }
+            }
+        }
+
+        Ok(columns)
+    }
+}
+
+impl ComparisonColumn {
+    /// The type of the metric being compared.
+    pub fn metric_type(&self) -> &MetricType {
+        &self.metric_type
+    }
+
+    /// The index of the compared value in a row's flat metric values.
+    pub fn other(&self) -> usize {
+        self.other
+    }
+
+    /// The comparison for a row's flat metric values, `None` for a percent change from zero
+    /// or when either value is missing, as an empty string.
+    ///
+    /// On failure, returns the index of the value that isn't a number.
+    pub fn value(&self, values: &[&String]) -> Result<Option<Number>, usize> {
+        let base = values[self.base];
+        let other = values[self.other];
+        if base.is_empty() || other.is_empty() {
+            return Ok(None);
+        }
+
+        // integer deltas too large for an i64 are left to the float comparison
+        if self.kind == ComparisonKind::Delta && self.metric_type == MetricType::Integer {
+            if let (Ok(base), Ok(other)) = (i64::from_str(base), i64::from_str(other)) {
+                if let Some(delta) = other.checked_sub(base) {
+                    return Ok(Some(Number::from(delta)));
+                }
+            }
+        }
+
+        let base = f64::from_str(base).map_err(|_| self.base)?;
+        let other = f64::from_str(other).map_err(|_| self.other)?;
+
+        let comparison = match self.kind {
+            ComparisonKind::Delta => other - base,
+            ComparisonKind::PercentChange if base == 0.0 => return Ok(None),
+            ComparisonKind::PercentChange => (other - base) / base.abs() * 100.0,
+        };
+
+        Ok(Number::from_f64(comparison))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column_header(metric_types: &[(&str, MetricType)]) -> ColumnHeader {
+        serde_json::from_value(json!({
+            "dimensions": ["ga:country"],
+            "metricHeader": {
+                "metricHeaderEntries": metric_types
+                    .iter()
+                    .map(|(name, metric_type)| json!({"name": name, "type": metric_type}))
+                    .collect::<Vec<_>>()
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn compares_each_date_range_to_the_base() {
+        let column_header = column_header(&[
+            ("ga:sessions", MetricType::Integer),
+            ("ga:bounceRate", MetricType::Percent),
+        ]);
+        let metric_headers = column_header.get_metric_headers(3);
+
+        let columns = Comparison { base_date_range: 1 }
+            .columns(&column_header, &metric_headers, 3, 0)
+            .unwrap();
+
+        let names: Vec<&str> = columns.iter().map(|column| column.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "ga:sessions_delta",
+                "ga:sessions_pct_change",
+                "ga:bounceRate_delta",
+                "ga:sessions_3_delta",
+                "ga:sessions_3_pct_change",
+                "ga:bounceRate_3_delta",
+            ]
+        );
+
+        let values: Vec<String> = vec!["10", "50.5", "8", "40.25", "0", "0"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let values: Vec<&String> = values.iter().collect();
+        let compared: Vec<Option<Number>> = columns
+            .iter()
+            .map(|column| column.value(&values).unwrap())
+            .collect();
+
+        assert_eq!(
+            compared,
+            vec![
+                Some(Number::from(2)),
+                Number::from_f64(25.0),
+                Number::from_f64(10.25),
+                Some(Number::from(-8)),
+                Number::from_f64(-100.0),
+                Number::from_f64(-40.25),
+            ]
+        );
+    }
+
+    #[test]
+    fn percent_change_from_zero_is_empty() {
+        let column_header = column_header(&[("ga:sessions", MetricType::Integer)]);
+        let metric_headers = column_header.get_metric_headers(2);
+        let columns = Comparison::default()
+            .columns(&column_header, &metric_headers, 2, 0)
+            .unwrap();
+
+        let values: Vec<String> = vec!["0".to_string(), "3".to_string()];
+        let values: Vec<&String> = values.iter().collect();
+
+        assert_eq!(columns[1].value(&values), Ok(None));
+    }
+
+    #[test]
+    fn missing_values_have_no_comparison() {
+        let column_header = column_header(&[("ga:sessions", MetricType::Integer)]);
+        let metric_headers = column_header.get_metric_headers(2);
+        let columns = Comparison::default()
+            .columns(&column_header, &metric_headers, 2, 0)
+            .unwrap();
+
+        let values: Vec<String> = vec!["5".to_string(), String::new()];
+        let values: Vec<&String> = values.iter().collect();
+
+        assert_eq!(columns[0].value(&values), Ok(None));
+        assert_eq!(columns[1].value(&values), Ok(None));
+    }
+
+    #[test]
+    fn integer_deltas_out_of_range_are_floats() {
+        let column_header = column_header(&[("ga:sessions", MetricType::Integer)]);
+        let metric_headers = column_header.get_metric_headers(2);
+        let columns = Comparison::default()
+            .columns(&column_header, &metric_headers, 2, 0)
+            .unwrap();
+
+        let values: Vec<String> = vec![i64::MIN.to_string(), i64::MAX.to_string()];
+        let values: Vec<&String> = values.iter().collect();
+
+        assert_eq!(
+            columns[0].value(&values),
+            Ok(Number::from_f64(i64::MAX as f64 - i64::MIN as f64))
+        );
+    }
+
+    #[test]
+    fn rejects_missing_base_date_range() {
+        let column_header = column_header(&[("ga:sessions", MetricType::Integer)]);
+        let metric_headers = column_header.get_metric_headers(2);
+
+        match (Comparison { base_date_range: 2 }).columns(&column_header, &metric_headers, 2, 0) {
+            Err(FlattenError::UnsupportedFeature(_)) => (),
+            other => panic!("expected an unsupported feature, got {:?}", other),
+        }
+    }
+}
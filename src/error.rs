@@ -0,0 +1,212 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Where in a report response a problem was found.
+///
+/// `column` is the header of the offending column, when there is one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub report: usize,
+    pub row: usize,
+    pub column: Option<String>,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "report {}, row {}", self.report, self.row)?;
+        if let Some(column) = &self.column {
+            write!(f, ", column \"{}\"", column)?;
+        }
+        Ok(())
+    }
+}
+
+/// Which of a report's columns a `ColumnHeaderMismatch` is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderKind {
+    Dimension,
+    Metric,
+}
+
+impl fmt::Display for HeaderKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeaderKind::Dimension => write!(f, "dimension"),
+            HeaderKind::Metric => write!(f, "metric"),
+        }
+    }
+}
+
+/// How the column at a `ColumnHeaderMismatch`'s position differs from the declared one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderMismatch {
+    /// The report has `found` where `expected` was declared
+    Different { expected: String, found: String },
+    /// The report has fewer columns than declared
+    Missing { expected: String },
+    /// The report has more columns than declared
+    Undeclared { found: String },
+}
+
+/// Why a report couldn't be flattened. Features add variants of their own, so matches need a
+/// catch-all arm.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum FlattenError {
+    /// The input could not be parsed as a report response
+    Json(serde_json::Error),
+    /// The report uses something that cannot be represented in the requested output
+    UnsupportedFeature(String),
+    /// A row has a different number of dimension or metric values than the column header
+    ShapeMismatch {
+        location: Location,
+        expected: usize,
+        found: usize,
+    },
+    /// A metric value could not be read as a number of its metric type
+    InvalidMetricValue { location: Location, value: String },
+    /// The pages of a paginated response don't describe the same reports. `report` is the
+    /// report whose column header differs, or `None` when the number of reports differs.
+    PageMismatch { page: usize, report: Option<usize> },
+    /// A different number of date range labels was given than the report has date ranges
+    DateRangeMismatch {
+        report: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// A row could not be deserialized into the requested type. `column` is only missing from
+    /// the location when the row as a whole didn't fit, like when a field has no column.
+    Deserialize { location: Location, message: String },
+    /// A report's dimensions or metrics aren't the ones a `GaReportRow` type declares.
+    /// `position` is the index of the first column that differs among those of its `kind`.
+    ColumnHeaderMismatch {
+        report: usize,
+        kind: HeaderKind,
+        position: usize,
+        mismatch: HeaderMismatch,
+    },
+    /// A GA4 pivot row's dimension values aren't a combination the pivot headers list, or
+    /// repeat an earlier row's when `duplicate`, so the wide layout has no cells of its own
+    /// for its values
+    UnexpectedPivotRow { location: Location, duplicate: bool },
+    /// Output could not be written
+    Io(io::Error),
+    /// An Arrow record batch could not be built or written
+    #[cfg(feature = "arrow")]
+    Arrow(arrow::error::ArrowError),
+    /// A Parquet file could not be written
+    #[cfg(feature = "parquet")]
+    Parquet(parquet::errors::ParquetError),
+}
+
+impl fmt::Display for FlattenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FlattenError::Json(err) => write!(f, "invalid report response: {}", err),
+            FlattenError::UnsupportedFeature(feature) => write!(f, "unsupported: {}", feature),
+            FlattenError::ShapeMismatch {
+                location,
+                expected,
+                found,
+            } => write!(
+                f,
+                "expected {} values but found {} at {}",
+                expected, found, location
+            ),
+            FlattenError::InvalidMetricValue { location, value } => {
+                write!(f, "invalid metric value \"{}\" at {}", value, location)
+            }
+            FlattenError::PageMismatch { page, report: None } => write!(
+                f,
+                "page {} has a different number of reports than the first page",
+                page
+            ),
+            FlattenError::PageMismatch {
+                page,
+                report: Some(report),
+            } => write!(
+                f,
+                "report {} on page {} has a different column header than on the first page",
+                report, page
+            ),
+            FlattenError::DateRangeMismatch {
+                report,
+                expected,
+                found,
+            } => write!(
+                f,
+                "report {} has {} date ranges but {} date range labels were given",
+                report, expected, found
+            ),
+            FlattenError::Deserialize { location, message } => {
+                write!(f, "failed to deserialize {}: {}", location, message)
+            }
+            FlattenError::ColumnHeaderMismatch {
+                report,
+                kind,
+                position,
+                mismatch,
+            } => match mismatch {
+                HeaderMismatch::Different { expected, found } => write!(
+                    f,
+                    "report {} has {} \"{}\" at position {} where \"{}\" was declared",
+                    report, kind, found, position, expected
+                ),
+                HeaderMismatch::Missing { expected } => write!(
+                    f,
+                    "report {} is missing declared {} \"{}\" at position {}",
+                    report, kind, expected, position
+                ),
+                HeaderMismatch::Undeclared { found } => write!(
+                    f,
+                    "report {} has undeclared {} \"{}\" at position {}",
+                    report, kind, found, position
+                ),
+            },
+            FlattenError::UnexpectedPivotRow {
+                location,
+                duplicate: false,
+            } => write!(
+                f,
+                "dimension values not listed in the pivot headers at {}",
+                location
+            ),
+            FlattenError::UnexpectedPivotRow {
+                location,
+                duplicate: true,
+            } => write!(f, "repeated pivot dimension values at {}", location),
+            FlattenError::Io(err) => write!(f, "failed to write output: {}", err),
+            #[cfg(feature = "arrow")]
+            FlattenError::Arrow(err) => write!(f, "arrow error: {}", err),
+            #[cfg(feature = "parquet")]
+            FlattenError::Parquet(err) => write!(f, "parquet error: {}", err),
+        }
+    }
+}
+
+impl error::Error for FlattenError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            FlattenError::Json(err) => Some(err),
+            FlattenError::Io(err) => Some(err),
+            #[cfg(feature = "arrow")]
+            FlattenError::Arrow(err) => Some(err),
+            #[cfg(feature = "parquet")]
+            FlattenError::Parquet(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for FlattenError {
+    fn from(err: serde_json::Error) -> Self {
+        FlattenError::Json(err)
+    }
+}
+
+impl From<io::Error> for FlattenError {
+    fn from(err: io::Error) -> Self {
+        FlattenError::Io(err)
+    }
+}
@@ -0,0 +1,643 @@
+use crate::comparison::{Comparison, ComparisonColumn};
+use crate::error::{FlattenError, Location};
+use crate::types::*;
+use serde_json::Number;
+use std::str::FromStr;
+
+/// What a `FlatTable` column holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnKind {
+    Dimension,
+    /// The date range of each row in the long layout, see `DATE_RANGE_COLUMN`
+    DateRange,
+    Metric,
+    /// A comparison between date ranges, see `Comparison`
+    Comparison,
+    /// The step number of rows exploded from conversion paths, a dimension whose cells are
+    /// numbers, see `mcf::STEP_COLUMN`
+    Step,
+}
+
+impl ColumnKind {
+    /// Whether the column tells rows apart, rather than holding values computed for them.
+    pub fn is_dimension(self) -> bool {
+        matches!(self, ColumnKind::Dimension | ColumnKind::Step)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    pub kind: ColumnKind,
+    /// The type of a metric column's values, or of the metric a comparison column compares
+    pub metric_type: Option<MetricType>,
+    /// The index, from 0, of the date range a metric column's values are from in the wide
+    /// layout, or of the date range a comparison column compares to the base
+    pub date_range: Option<usize>,
+}
+
+/// A single value in a `FlatTable`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell {
+    /// A dimension value or date range label
+    Text(String),
+    /// A metric value as the API reported it, along with the number it is for the column's
+    /// metric type: an integer for `INTEGER` metrics and a float for the other types
+    Metric { text: String, number: Number },
+    /// A number worked out while flattening, like a date range number, a step number or a
+    /// comparison
+    Number(Number),
+    /// No value, like the dimensions of a summary row, a percent change from zero or a metric
+    /// left empty by a source that has missing values, see
+    /// `ReportSource::empty_metrics_are_missing`
+    Empty,
+}
+
+/// How a `FlatTable` is laid out. The options mean the same as those of the same names in
+/// `DelimitedOptions` and `FlatJsonOptions`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FlatTableOptions {
+    pub layout: Layout,
+    pub date_range_labels: Vec<Vec<String>>,
+    pub comparison: Option<Comparison>,
+}
+
+impl FlatTableOptions {
+    pub(crate) fn check(&self) -> Result<(), FlattenError> {
+        if self.comparison.is_some() && self.layout == Layout::Long {
+            return Err(FlattenError::UnsupportedFeature(
+                "date range comparisons in the long layout".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// A report flattened to named, typed columns and rows of cells, which every output format
+/// is written from.
+///
+/// Summaries are laid out like `rows`, with empty dimension cells: a single row, or a row
+/// for each date range in the long layout. They are `None` when the report doesn't have them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlatTable {
+    pub columns: Vec<Column>,
+    pub rows: Vec<Vec<Cell>>,
+    pub totals: Option<Vec<Vec<Cell>>>,
+    pub minimums: Option<Vec<Vec<Cell>>>,
+    pub maximums: Option<Vec<Vec<Cell>>>,
+    pub metadata: ReportMetadata,
+    pub layout: Layout,
+    pub number_of_date_ranges: usize,
+}
+
+impl FlatTable {
+    /// Flattens a report, the `report_index`th in its response.
+    pub fn from_report(
+        report: &Report,
+        report_index: usize,
+        options: &FlatTableOptions,
+    ) -> Result<FlatTable, FlattenError> {
+        let data = &report.data;
+        // rows have values for each date range, and so do the totals of reports without rows
+        let number_of_date_ranges = data
+            .rows
+            .first()
+            .map_or(data.totals.len(), |row| row.metrics.len());
+        let flattener = RowFlattener::new(
+            &report.column_header,
+            &report.source,
+            report_index,
+            number_of_date_ranges,
+            options,
+        )?;
+
+        let mut rows = Vec::with_capacity(data.rows.len());
+        for (row_index, row) in data.rows.iter().enumerate() {
+            flattener.flatten_row(row, row_index, |cells| {
+                rows.push(cells);
+                Ok(())
+            })?;
+        }
+        let [totals, minimums, maximums] = flattener.flatten_summaries(data, data.rows.len())?;
+
+        Ok(FlatTable {
+            columns: flattener.columns,
+            rows,
+            totals,
+            minimums,
+            maximums,
+            metadata: report.metadata(),
+            layout: options.layout,
+            number_of_date_ranges,
+        })
+    }
+
+    /// The index of the report row that the row at `table_row` was flattened from.
+    pub fn report_row_index(&self, table_row: usize) -> usize {
+        match self.layout {
+            Layout::Wide => table_row,
+            Layout::Long => table_row / self.number_of_date_ranges.max(1),
+        }
+    }
+}
+
+// turns report rows into table rows a row at a time, so the same table can be
+// built from a parsed Report or from rows as they are streamed in
+pub(crate) struct RowFlattener {
+    report_index: usize,
+    layout: Layout,
+    dimension_headers: Vec<String>,
+    metric_headers: Vec<MetricHeaderEntry>,
+    date_range_labels: Option<Vec<String>>,
+    comparison_columns: Vec<ComparisonColumn>,
+    empty_metrics_are_missing: bool,
+    step_dimension: Option<usize>,
+    columns: Vec<Column>,
+}
+
+impl RowFlattener {
+    pub(crate) fn new(
+        column_header: &ColumnHeader,
+        source: &ReportSource,
+        report_index: usize,
+        number_of_date_ranges: usize,
+        options: &FlatTableOptions,
+    ) -> Result<Self, FlattenError> {
+        options.check()?;
+
+        let layout = options.layout;
+        let date_range_labels = report_date_range_labels(
+            &options.date_range_labels,
+            report_index,
+            number_of_date_ranges,
+        )?;
+        let metric_headers = column_header.get_layout_metric_headers(
+            layout,
+            number_of_date_ranges,
+            date_range_labels,
+        );
+        let comparison_columns = match options.comparison {
+            Some(comparison) => comparison.columns(
+                column_header,
+                &metric_headers,
+                number_of_date_ranges,
+                report_index,
+            )?,
+            None => Vec::new(),
+        };
+
+        let metrics_per_date_range = column_header.metric_header.flat_header_entries().len();
+        let date_range_of = |value_index: usize| match layout {
+            Layout::Wide => Some(value_index / metrics_per_date_range),
+            Layout::Long => None,
+        };
+
+        let step_dimension = source.step_dimension;
+        let dimension_columns = column_header
+            .dimensions
+            .iter()
+            .enumerate()
+            .map(|(i, name)| Column {
+                name: name.clone(),
+                kind: if step_dimension == Some(i) {
+                    ColumnKind::Step
+                } else {
+                    ColumnKind::Dimension
+                },
+                metric_type: None,
+                date_range: None,
+            });
+        let date_range_column = match layout {
+            Layout::Wide => None,
+            Layout::Long => Some(Column {
+                name: DATE_RANGE_COLUMN.to_string(),
+                kind: ColumnKind::DateRange,
+                metric_type: None,
+                date_range: None,
+            }),
+        };
+        let metric_columns = metric_headers.iter().enumerate().map(|(i, header)| Column {
+            name: header.name.clone(),
+            kind: ColumnKind::Metric,
+            metric_type: Some(header.metric_type.clone()),
+            date_range: date_range_of(i),
+        });
+        let comparison_table_columns = comparison_columns.iter().map(|column| Column {
+            name: column.name.clone(),
+            kind: ColumnKind::Comparison,
+            metric_type: Some(column.metric_type().clone()),
+            date_range: date_range_of(column.other()),
+        });
+        let columns = dimension_columns
+            .chain(date_range_column)
+            .chain(metric_columns)
+            .chain(comparison_table_columns)
+            .collect();
+
+        Ok(RowFlattener {
+            report_index,
+            layout,
+            dimension_headers: column_header.dimensions.clone(),
+            metric_headers,
+            date_range_labels: date_range_labels.map(<[String]>::to_vec),
+            comparison_columns,
+            empty_metrics_are_missing: source.empty_metrics_are_missing,
+            step_dimension,
+            columns,
+        })
+    }
+
+    pub(crate) fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    /// Calls `emit` with the cells of the row, or with those of each of its date ranges in
+    /// the long layout.
+    pub(crate) fn flatten_row<F>(
+        &self,
+        row: &ReportRow,
+        row_index: usize,
+        emit: F,
+    ) -> Result<(), FlattenError>
+    where
+        F: FnMut(Vec<Cell>) -> Result<(), FlattenError>,
+    {
+        self.flatten(row, false, row_index, emit)
+    }
+
+    // totals, minimums and maximums, in that order, numbered on from first_row_index
+    pub(crate) fn flatten_summaries(
+        &self,
+        data: &ReportData,
+        first_row_index: usize,
+    ) -> Result<[Option<Vec<Vec<Cell>>>; 3], FlattenError> {
+        let mut row_index = first_row_index;
+        let mut summary = |values: Option<&Vec<DateRangeValue>>| -> Result<_, FlattenError> {
+            match values {
+                Some(values) if !values.is_empty() => {
+                    // summary values are flattened like any other row, without dimension values
+                    let summary_row = ReportRow {
+                        dimensions: vec![String::new(); self.dimension_headers.len()],
+                        metrics: values.to_vec(),
+                    };
+                    let mut rows = Vec::new();
+                    self.flatten(&summary_row, true, row_index, |cells| {
+                        rows.push(cells);
+                        Ok(())
+                    })?;
+                    row_index += 1;
+
+                    Ok(Some(rows))
+                }
+                _ => Ok(None),
+            }
+        };
+
+        Ok([
+            summary(Some(&data.totals))?,
+            summary(data.minimums.as_ref())?,
+            summary(data.maximums.as_ref())?,
+        ])
+    }
+
+    fn flatten<F>(
+        &self,
+        row: &ReportRow,
+        is_summary: bool,
+        row_index: usize,
+        mut emit: F,
+    ) -> Result<(), FlattenError>
+    where
+        F: FnMut(Vec<Cell>) -> Result<(), FlattenError>,
+    {
+        // every row must have a label for each of its date ranges, not just the first
+        if let Some(labels) = &self.date_range_labels {
+            if labels.len() != row.metrics.len() {
+                return Err(FlattenError::DateRangeMismatch {
+                    report: self.report_index,
+                    expected: row.metrics.len(),
+                    found: labels.len(),
+                });
+            }
+        }
+        row.check_shape(
+            &self.dimension_headers,
+            &self.metric_headers,
+            self.layout,
+            self.report_index,
+            row_index,
+        )?;
+
+        let dimension_cells = || {
+            row.dimensions.iter().enumerate().map(|(i, value)| {
+                if is_summary {
+                    Cell::Empty
+                } else if self.step_dimension == Some(i) {
+                    // steps are numbered while parsing, so only look like anything else
+                    // when a report was built by hand
+                    u64::from_str(value).map_or_else(
+                        |_| Cell::Text(value.clone()),
+                        |step| Cell::Number(Number::from(step)),
+                    )
+                } else {
+                    Cell::Text(value.clone())
+                }
+            })
+        };
+
+        match self.layout {
+            Layout::Wide => {
+                let values: Vec<&String> = row.flat_value_iterator().collect();
+
+                let mut cells: Vec<Cell> = Vec::with_capacity(self.columns.len());
+                cells.extend(dimension_cells());
+                self.push_metric_cells(&mut cells, values.iter().cloned(), row_index)?;
+                for column in self.comparison_columns.iter() {
+                    let comparison = column.value(&values).map_err(|index| {
+                        self.invalid_metric_value(row_index, index, values[index])
+                    })?;
+                    cells.push(comparison.map_or(Cell::Empty, Cell::Number));
+                }
+
+                emit(cells)
+            }
+            Layout::Long => {
+                for (i, date_range_value) in row.metrics.iter().enumerate() {
+                    let date_range = match &self.date_range_labels {
+                        Some(labels) => Cell::Text(labels[i].clone()),
+                        None => Cell::Number(Number::from(i + 1)),
+                    };
+
+                    let mut cells: Vec<Cell> = Vec::with_capacity(self.columns.len());
+                    cells.extend(dimension_cells());
+                    cells.push(date_range);
+                    self.push_metric_cells(
+                        &mut cells,
+                        date_range_value.flat_value_iterator(),
+                        row_index,
+                    )?;
+
+                    emit(cells)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    fn push_metric_cells<'a>(
+        &self,
+        cells: &mut Vec<Cell>,
+        values: impl Iterator<Item = &'a String>,
+        row_index: usize,
+    ) -> Result<(), FlattenError> {
+        for (i, (header, value)) in self.metric_headers.iter().zip(values).enumerate() {
+            if value.is_empty() && self.empty_metrics_are_missing {
+                cells.push(Cell::Empty);
+                continue;
+            }
+            let number = metric_number(value, &header.metric_type)
+                .ok_or_else(|| self.invalid_metric_value(row_index, i, value))?;
+            cells.push(Cell::Metric {
+                text: value.to_string(),
+                number,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn invalid_metric_value(
+        &self,
+        row_index: usize,
+        metric_index: usize,
+        value: &str,
+    ) -> FlattenError {
+        FlattenError::InvalidMetricValue {
+            location: Location {
+                report: self.report_index,
+                row: row_index,
+                column: Some(self.metric_headers[metric_index].name.clone()),
+            },
+            value: value.to_string(),
+        }
+    }
+}
+
+// integers that don't fit an i64 and untyped metrics are whatever number they look like
+fn metric_number(value: &str, metric_type: &MetricType) -> Option<Number> {
+    match metric_type {
+        MetricType::Integer => match i64::from_str(value) {
+            Ok(integer) => Some(Number::from(integer)),
+            Err(_) => Number::from_str(value).ok(),
+        },
+        MetricType::Float | MetricType::Currency | MetricType::Percent | MetricType::Time => {
+            f64::from_str(value).ok().and_then(Number::from_f64)
+        }
+        MetricType::MetricTypeUnspecified => Number::from_str(value).ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    fn read_response(name: &str) -> ReportResponse {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("test_reports")
+                .join(name),
+        )
+        .unwrap();
+
+        serde_json::from_str(data.as_str()).unwrap()
+    }
+
+    fn text(value: &str) -> Cell {
+        Cell::Text(value.to_string())
+    }
+
+    fn metric(text: &str, number: Number) -> Cell {
+        Cell::Metric {
+            text: text.to_string(),
+            number,
+        }
+    }
+
+    #[test]
+    fn columns_and_typed_cells() {
+        let response = read_response("metric_types.json");
+
+        let table = FlatTable::from_report(&response.reports[0], 0, &Default::default()).unwrap();
+
+        let columns: Vec<(&str, ColumnKind, Option<MetricType>, Option<usize>)> = table
+            .columns
+            .iter()
+            .map(|column| {
+                (
+                    column.name.as_str(),
+                    column.kind,
+                    column.metric_type.clone(),
+                    column.date_range,
+                )
+            })
+            .collect();
+        assert_eq!(
+            columns,
+            vec![
+                ("ga:country", ColumnKind::Dimension, None, None),
+                (
+                    "ga:sessions",
+                    ColumnKind::Metric,
+                    Some(MetricType::Integer),
+                    Some(0)
+                ),
+                (
+                    "ga:bounceRate",
+                    ColumnKind::Metric,
+                    Some(MetricType::Percent),
+                    Some(0)
+                ),
+                (
+                    "ga:transactionRevenue",
+                    ColumnKind::Metric,
+                    Some(MetricType::Currency),
+                    Some(0)
+                ),
+                (
+                    "ga:pageviewsPerSession",
+                    ColumnKind::Metric,
+                    Some(MetricType::Float),
+                    Some(0)
+                ),
+                (
+                    "ga:avgSessionDuration",
+                    ColumnKind::Metric,
+                    Some(MetricType::Time),
+                    Some(0)
+                ),
+            ]
+        );
+        assert_eq!(
+            table.rows,
+            vec![vec![
+                text("Canada"),
+                metric("12", Number::from(12)),
+                metric("50", Number::from_f64(50.0).unwrap()),
+                metric("10", Number::from_f64(10.0).unwrap()),
+                metric("2.5", Number::from_f64(2.5).unwrap()),
+                metric("3725.5", Number::from_f64(3725.5).unwrap()),
+            ]]
+        );
+    }
+
+    #[test]
+    fn long_layout_with_summaries() {
+        let response = read_response("multiple_date_ranges.json");
+        let report = &response.reports[0];
+
+        let table = FlatTable::from_report(
+            report,
+            0,
+            &FlatTableOptions {
+                layout: Layout::Long,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(table.number_of_date_ranges, 2);
+        assert_eq!(table.columns[1].kind, ColumnKind::DateRange);
+        assert_eq!(table.columns[2].date_range, None);
+        assert_eq!(table.rows.len(), report.data.rows.len() * 2);
+        assert_eq!(table.rows[1][1], Cell::Number(Number::from(2)));
+        assert_eq!(table.report_row_index(3), 1);
+
+        let totals = table.totals.unwrap();
+        assert_eq!(totals.len(), 2);
+        assert_eq!(totals[0][0], Cell::Empty);
+        assert_eq!(totals[0][1], Cell::Number(Number::from(1)));
+        assert_eq!(table.minimums.map(|rows| rows.len()), Some(2));
+    }
+
+    #[test]
+    fn comparison_columns() {
+        let response = read_response("multiple_date_ranges.json");
+
+        let table = FlatTable::from_report(
+            &response.reports[0],
+            0,
+            &FlatTableOptions {
+                comparison: Some(Comparison::default()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let comparisons: Vec<&Column> = table
+            .columns
+            .iter()
+            .filter(|column| column.kind == ColumnKind::Comparison)
+            .collect();
+        assert!(!comparisons.is_empty());
+        assert!(comparisons
+            .iter()
+            .all(|column| column.date_range == Some(1) && column.metric_type.is_some()));
+    }
+
+    #[test]
+    fn columns_without_rows() {
+        let response = read_response("no_rows.json");
+
+        let table = FlatTable::from_report(&response.reports[0], 0, &Default::default()).unwrap();
+
+        let names: Vec<&str> = table
+            .columns
+            .iter()
+            .map(|column| column.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["ga:deviceCategory", "ga:sessions"]);
+        assert!(table.rows.is_empty());
+        assert_eq!(
+            table.totals,
+            Some(vec![vec![Cell::Empty, metric("0", Number::from(0))]])
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_metric_values() {
+        let response = read_response("invalid_metric_value.json");
+
+        match FlatTable::from_report(&response.reports[0], 0, &Default::default()) {
+            Err(FlattenError::InvalidMetricValue { location, value }) => {
+                assert_eq!(location.row, 1);
+                assert_eq!(location.column, Some("ga:sessions".to_string()));
+                assert_eq!(value, "n/a");
+            }
+            other => panic!("expected an invalid metric value, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn empty_metrics_are_only_missing_when_the_source_says_so() {
+        let mut response = read_response("single_dimension_and_metric.json");
+        let report = &mut response.reports[0];
+        report.data.rows[1].metrics[0].values[0] = String::new();
+
+        match FlatTable::from_report(report, 0, &Default::default()) {
+            Err(FlattenError::InvalidMetricValue { location, value }) => {
+                assert_eq!(location.row, 1);
+                assert_eq!(location.column, Some("ga:sessions".to_string()));
+                assert_eq!(value, "");
+            }
+            other => panic!("expected an invalid metric value, got {:?}", other),
+        }
+
+        report.source.empty_metrics_are_missing = true;
+        let table = FlatTable::from_report(report, 0, &Default::default()).unwrap();
+        assert_eq!(table.rows[1], vec![text("mobile"), Cell::Empty]);
+    }
+}
@@ -0,0 +1,472 @@
+use crate::error::{FlattenError, Location};
+use crate::types::*;
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, Unexpected, Visitor,
+};
+use serde::forward_to_deserialize_any;
+use std::fmt;
+use std::str::FromStr;
+
+/// How report columns are matched to the fields of the type rows are deserialized into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FromReportOptions {
+    /// Stripped from the start of dimension and metric names, so `ga:sessions` fills a
+    /// `sessions` field. `None` matches fields to the names as they are.
+    pub strip_prefix: Option<String>,
+}
+
+impl Default for FromReportOptions {
+    fn default() -> Self {
+        FromReportOptions {
+            strip_prefix: Some("ga:".to_string()),
+        }
+    }
+}
+
+/// Deserializes each row of the report into a `T`, see `from_report_with_options`.
+pub fn from_report<T: DeserializeOwned>(report: &Report) -> Result<Vec<T>, FlattenError> {
+    from_report_with_options(report, &FromReportOptions::default())
+}
+
+/// Deserializes each row of the report into a `T`, straight from the row's values.
+///
+/// Rows are maps from column names, with the `ga:` prefix stripped by default, to values.
+/// Metric columns for later date ranges are suffixed like in the other outputs, e.g.
+/// `sessions_2`. Dimensions deserialize as strings and metrics as whatever number type the
+/// field has, or as an integer or a float by metric type when the field takes any value.
+/// Errors are located as if the report were the first in its response.
+pub fn from_report_with_options<T: DeserializeOwned>(
+    report: &Report,
+    options: &FromReportOptions,
+) -> Result<Vec<T>, FlattenError> {
+    deserialize_rows(report, 0, |name| {
+        options
+            .strip_prefix
+            .as_deref()
+            .and_then(|prefix| name.strip_prefix(prefix))
+            .unwrap_or(name)
+            .to_string()
+    })
+}
+
+// `field_name` gives the field each column fills from its header
+pub(crate) fn deserialize_rows<T: DeserializeOwned>(
+    report: &Report,
+    report_index: usize,
+    field_name: impl Fn(&str) -> String,
+) -> Result<Vec<T>, FlattenError> {
+    let dimension_headers = &report.column_header.dimensions;
+    let metric_headers = report.get_metric_headers();
+    let columns: Vec<ColumnDescription> = dimension_headers
+        .iter()
+        .map(|name| ColumnDescription {
+            header: name,
+            field: field_name(name),
+            metric_type: None,
+        })
+        .chain(metric_headers.iter().map(|entry| ColumnDescription {
+            header: &entry.name,
+            field: field_name(&entry.name),
+            metric_type: Some(&entry.metric_type),
+        }))
+        .collect();
+
+    report
+        .data
+        .rows
+        .iter()
+        .enumerate()
+        .map(|(row_index, row)| {
+            row.check_shape(
+                dimension_headers,
+                &metric_headers,
+                Layout::Wide,
+                report_index,
+                row_index,
+            )?;
+
+            let mut current_column = None;
+            let values = row.dimensions.iter().chain(row.flat_value_iterator());
+            let deserializer = RowDeserializer {
+                columns: &columns,
+                values: values.collect(),
+                current_column: &mut current_column,
+            };
+
+            T::deserialize(deserializer).map_err(|DeError(message)| FlattenError::Deserialize {
+                location: Location {
+                    report: report_index,
+                    row: row_index,
+                    column: current_column.map(str::to_string),
+                },
+                message,
+            })
+        })
+        .collect()
+}
+
+struct ColumnDescription<'r> {
+    header: &'r str,
+    field: String,
+    metric_type: Option<&'r MetricType>,
+}
+
+#[derive(Debug)]
+struct DeError(String);
+
+impl fmt::Display for DeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for DeError {}
+
+impl de::Error for DeError {
+    fn custom<M: fmt::Display>(message: M) -> Self {
+        DeError(message.to_string())
+    }
+}
+
+// a row is a map from field names to its values, in column order
+struct RowDeserializer<'c, 'r> {
+    columns: &'c [ColumnDescription<'r>],
+    values: Vec<&'r String>,
+    // the header of the column being deserialized, so errors can say where they happened
+    current_column: &'c mut Option<&'r str>,
+}
+
+impl<'de, 'c, 'r> de::Deserializer<'de> for RowDeserializer<'c, 'r> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_map(RowAccess {
+            columns: self.columns,
+            values: self.values,
+            index: 0,
+            current_column: self.current_column,
+        })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map struct enum
+        identifier ignored_any
+    }
+}
+
+struct RowAccess<'c, 'r> {
+    columns: &'c [ColumnDescription<'r>],
+    values: Vec<&'r String>,
+    index: usize,
+    current_column: &'c mut Option<&'r str>,
+}
+
+impl<'de, 'c, 'r> MapAccess<'de> for RowAccess<'c, 'r> {
+    type Error = DeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, DeError> {
+        match self.columns.get(self.index) {
+            Some(column) => {
+                *self.current_column = Some(column.header);
+                seed.deserialize(column.field.as_str().into_deserializer())
+                    .map(Some)
+            }
+            None => {
+                *self.current_column = None;
+                Ok(None)
+            }
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, DeError> {
+        let column = &self.columns[self.index];
+        let value = self.values[self.index];
+        self.index += 1;
+
+        seed.deserialize(ValueDeserializer {
+            text: value,
+            metric_type: column.metric_type,
+        })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.columns.len() - self.index)
+    }
+}
+
+// a single dimension or metric value, parsed into whatever the field asks for
+struct ValueDeserializer<'r> {
+    text: &'r str,
+    metric_type: Option<&'r MetricType>,
+}
+
+impl<'r> ValueDeserializer<'r> {
+    fn parse<'de, T: FromStr, V: Visitor<'de>>(
+        &self,
+        visitor: V,
+        visit: impl FnOnce(V, T) -> Result<V::Value, DeError>,
+    ) -> Result<V::Value, DeError> {
+        match T::from_str(self.text) {
+            Ok(value) => visit(visitor, value),
+            Err(_) => Err(de::Error::invalid_value(
+                Unexpected::Str(self.text),
+                &visitor,
+            )),
+        }
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+                self.parse(visitor, |visitor, value| visitor.$visit(value))
+            }
+        )*
+    };
+}
+
+impl<'de, 'r> de::Deserializer<'de> for ValueDeserializer<'r> {
+    type Error = DeError;
+
+    // without a type to aim for, dimensions are strings and metrics are numbers
+    // like in the flat JSON output
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match self.metric_type {
+            None => visitor.visit_str(self.text),
+            Some(MetricType::Integer) | Some(MetricType::MetricTypeUnspecified) => {
+                match i64::from_str(self.text) {
+                    Ok(integer) => visitor.visit_i64(integer),
+                    Err(_) => self.parse(visitor, |visitor, value| visitor.visit_f64(value)),
+                }
+            }
+            Some(_) => self.parse(visitor, |visitor, value| visitor.visit_f64(value)),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_str(self.text)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_str(self.text)
+    }
+
+    // a metric can be missing, as an empty string, like in a GA4 pivot
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        if self.metric_type.is_some() && self.text.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // dimension values like `desktop` can fill unit variants of an enum
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        visitor.visit_enum(self.text.into_deserializer())
+    }
+
+    // columns the target doesn't have a field for aren't parsed, so a metric that
+    // wouldn't fit any field can't fail a row that doesn't ask for it
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        char bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    fn read_response(name: &str) -> ReportResponse {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("test_reports")
+                .join(name),
+        )
+        .unwrap();
+
+        serde_json::from_str(data.as_str()).unwrap()
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    struct CountryRow {
+        country: String,
+        sessions: u32,
+        bounce_rate: f64,
+        transaction_revenue: f32,
+        avg_session_duration: f64,
+    }
+
+    #[test]
+    fn deserializes_rows_into_structs() {
+        let response = read_response("metric_types.json");
+
+        let rows: Vec<CountryRow> = from_report(&response.reports[0]).unwrap();
+
+        assert_eq!(
+            rows,
+            vec![CountryRow {
+                country: "Canada".to_string(),
+                sessions: 12,
+                bounce_rate: 50.0,
+                transaction_revenue: 10.0,
+                avg_session_duration: 3725.5,
+            }]
+        );
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum DeviceCategory {
+        Desktop,
+        Mobile,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct DeviceRow {
+        #[serde(rename = "ga:deviceCategory")]
+        device_category: DeviceCategory,
+        #[serde(rename = "ga:sessions")]
+        sessions: Option<i64>,
+    }
+
+    #[test]
+    fn keeps_prefixes_when_asked() {
+        let response = read_response("single_dimension_and_metric.json");
+
+        let rows: Vec<DeviceRow> = from_report_with_options(
+            &response.reports[0],
+            &FromReportOptions { strip_prefix: None },
+        )
+        .unwrap();
+
+        assert_eq!(
+            rows,
+            vec![
+                DeviceRow {
+                    device_category: DeviceCategory::Desktop,
+                    sessions: Some(43),
+                },
+                DeviceRow {
+                    device_category: DeviceCategory::Mobile,
+                    sessions: Some(1),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn later_date_ranges_are_suffixed() {
+        let response = read_response("multiple_date_ranges.json");
+        let report = &response.reports[0];
+
+        let rows: Vec<serde_json::Map<String, serde_json::Value>> = from_report(report).unwrap();
+
+        let field_names: Vec<&String> = rows[0].keys().collect();
+        assert!(field_names
+            .iter()
+            .any(|name| name.ends_with("_2") && !name.starts_with("ga:")));
+        assert_eq!(rows.len(), report.data.rows.len());
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct SessionsRow {
+        sessions: u8,
+    }
+
+    #[test]
+    fn locates_values_that_dont_fit_the_field() {
+        let response = read_response("invalid_metric_value.json");
+
+        match from_report::<SessionsRow>(&response.reports[0]) {
+            Err(FlattenError::Deserialize { location, message }) => {
+                assert_eq!(
+                    location,
+                    Location {
+                        report: 0,
+                        row: 1,
+                        column: Some("ga:sessions".to_string()),
+                    }
+                );
+                assert_eq!(message, "invalid value: string \"n/a\", expected u8");
+            }
+            other => panic!("expected a deserialization error, got {:?}", other),
+        }
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    struct DeviceCategoryRow {
+        device_category: String,
+    }
+
+    #[test]
+    fn skips_columns_without_a_field() {
+        let response = read_response("invalid_metric_value.json");
+
+        let rows: Vec<DeviceCategoryRow> = from_report(&response.reports[0]).unwrap();
+
+        assert_eq!(rows.len(), response.reports[0].data.rows.len());
+        assert_eq!(rows[1].device_category, "mobile");
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct MissingFieldRow {
+        users: u32,
+    }
+
+    #[test]
+    fn reports_missing_fields() {
+        let response = read_response("single_dimension_and_metric.json");
+
+        match from_report::<MissingFieldRow>(&response.reports[0]) {
+            Err(FlattenError::Deserialize { location, message }) => {
+                assert_eq!(location.column, None);
+                assert_eq!(message, "missing field `users`");
+            }
+            other => panic!("expected a deserialization error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,803 @@
+use crate::error::{FlattenError, Location};
+use crate::types::*;
+use serde::de::IgnoredAny;
+use std::collections::{HashMap, HashSet};
+
+/// The response of the GA4 Data API's `batchRunReports`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Ga4BatchResponse {
+    pub reports: Vec<Ga4Report>,
+    pub kind: Option<String>,
+}
+
+/// The response of the GA4 Data API's `runReport`, or one of the reports of `batchRunReports`.
+///
+/// GA4 has no separate date range values: with several date ranges each row has a
+/// `dateRange` dimension instead, and there is a total, minimum and maximum for each date
+/// range, which can't be flattened like v4 summaries, so asking for them is an error.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Ga4Report {
+    #[serde(default)]
+    pub dimension_headers: Vec<Ga4DimensionHeader>,
+    #[serde(default)]
+    pub metric_headers: Vec<Ga4MetricHeader>,
+    #[serde(default)]
+    pub rows: Vec<Ga4Row>,
+    #[serde(default)]
+    pub totals: Vec<Ga4Row>,
+    #[serde(default)]
+    pub maximums: Vec<Ga4Row>,
+    #[serde(default)]
+    pub minimums: Vec<Ga4Row>,
+    pub row_count: Option<u32>,
+    pub metadata: Option<Ga4ResponseMetadata>,
+    pub kind: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Ga4DimensionHeader {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Ga4MetricHeader {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub metric_type: Ga4MetricType,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Ga4MetricType {
+    #[default]
+    MetricTypeUnspecified,
+    TypeInteger,
+    TypeFloat,
+    TypeSeconds,
+    TypeMilliseconds,
+    TypeMinutes,
+    TypeHours,
+    TypeStandard,
+    TypeCurrency,
+    TypeFeet,
+    TypeMiles,
+    TypeMeters,
+    TypeKilometers,
+    #[serde(other)]
+    Other,
+}
+
+impl Ga4MetricType {
+    // only seconds line up with v4 TIME metrics, the other durations and
+    // distances are plain numbers in their own unit
+    pub fn to_metric_type(self) -> MetricType {
+        match self {
+            Ga4MetricType::TypeInteger => MetricType::Integer,
+            Ga4MetricType::TypeSeconds => MetricType::Time,
+            Ga4MetricType::TypeCurrency => MetricType::Currency,
+            Ga4MetricType::TypeFloat
+            | Ga4MetricType::TypeMilliseconds
+            | Ga4MetricType::TypeMinutes
+            | Ga4MetricType::TypeHours
+            | Ga4MetricType::TypeStandard
+            | Ga4MetricType::TypeFeet
+            | Ga4MetricType::TypeMiles
+            | Ga4MetricType::TypeMeters
+            | Ga4MetricType::TypeKilometers => MetricType::Float,
+            Ga4MetricType::MetricTypeUnspecified | Ga4MetricType::Other => {
+                MetricType::MetricTypeUnspecified
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Ga4Row {
+    #[serde(default)]
+    pub dimension_values: Vec<Ga4Value>,
+    #[serde(default)]
+    pub metric_values: Vec<Ga4Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Ga4Value {
+    #[serde(default)]
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Ga4ResponseMetadata {
+    pub sampling_metadatas: Option<Vec<Ga4SamplingMetadata>>,
+    pub data_loss_from_other_row: Option<bool>,
+    pub currency_code: Option<String>,
+    pub time_zone: Option<String>,
+    pub subject_to_thresholding: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Ga4SamplingMetadata {
+    pub samples_read_count: String,
+    pub sampling_space_size: String,
+}
+
+/// How a GA4 pivot report is laid out, see `Ga4PivotReport::to_report`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PivotLayout {
+    /// A row per combination of the first pivot's values, with a column per metric for each
+    /// combination of the other pivots' values
+    #[default]
+    Wide,
+    /// A row per pivot cell, with a column per dimension and metric
+    Long,
+}
+
+/// The response of the GA4 Data API's `batchRunPivotReports`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Ga4BatchPivotResponse {
+    pub pivot_reports: Vec<Ga4PivotReport>,
+    pub kind: Option<String>,
+}
+
+/// The response of the GA4 Data API's `runPivotReport`.
+///
+/// Each pivot's dimensions are expected to follow on from the previous pivot's in
+/// `dimension_headers`, and in each row's dimension values, as they do in the request.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Ga4PivotReport {
+    #[serde(default)]
+    pub pivot_headers: Vec<Ga4PivotHeader>,
+    #[serde(default)]
+    pub dimension_headers: Vec<Ga4DimensionHeader>,
+    #[serde(default)]
+    pub metric_headers: Vec<Ga4MetricHeader>,
+    #[serde(default)]
+    pub rows: Vec<Ga4Row>,
+    #[serde(default)]
+    pub aggregates: Vec<Ga4Row>,
+    pub metadata: Option<Ga4ResponseMetadata>,
+    pub kind: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Ga4PivotHeader {
+    #[serde(default)]
+    pub pivot_dimension_headers: Vec<Ga4PivotDimensionHeader>,
+    pub row_count: Option<u32>,
+}
+
+impl Ga4PivotHeader {
+    fn number_of_dimensions(&self) -> usize {
+        self.pivot_dimension_headers
+            .first()
+            .map_or(0, |header| header.dimension_values.len())
+    }
+
+    // the combinations of dimension values in this pivot, in the order the API returned them
+    fn combinations(&self) -> Vec<Vec<String>> {
+        self.pivot_dimension_headers
+            .iter()
+            .map(|header| ga4_values(&header.dimension_values))
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Ga4PivotDimensionHeader {
+    #[serde(default)]
+    pub dimension_values: Vec<Ga4Value>,
+}
+
+impl Ga4Row {
+    fn dimensions(&self) -> Vec<String> {
+        ga4_values(&self.dimension_values)
+    }
+
+    fn date_range_value(&self) -> DateRangeValue {
+        DateRangeValue {
+            values: ga4_values(&self.metric_values),
+            pivot_value_regions: vec![],
+        }
+    }
+}
+
+fn ga4_values(values: &[Ga4Value]) -> Vec<String> {
+    values.iter().map(|value| value.value.clone()).collect()
+}
+
+// with several date ranges there is a summary row per date range, which a v4
+// report can't hold, see ReportSource::summaries_by_date_range
+fn ga4_summary(rows: &[Ga4Row]) -> Option<Vec<DateRangeValue>> {
+    match rows {
+        [row] => Some(vec![row.date_range_value()]),
+        _ => None,
+    }
+}
+
+fn ga4_dimension_names(headers: &[Ga4DimensionHeader]) -> Vec<String> {
+    headers.iter().map(|header| header.name.clone()).collect()
+}
+
+fn ga4_metric_header_entries(headers: &[Ga4MetricHeader]) -> Vec<MetricHeaderEntry> {
+    headers
+        .iter()
+        .map(|header| MetricHeaderEntry {
+            name: header.name.clone(),
+            metric_type: header.metric_type.to_metric_type(),
+        })
+        .collect()
+}
+
+// the v4 sample read counts and sampling space sizes, one per sampled date range
+fn ga4_sampling(
+    metadata: Option<&Ga4ResponseMetadata>,
+) -> (Option<Vec<String>>, Option<Vec<String>>) {
+    let sampling_metadatas = metadata.and_then(|metadata| metadata.sampling_metadatas.as_ref());
+
+    (
+        sampling_metadatas.map(|metadatas| {
+            metadatas
+                .iter()
+                .map(|metadata| metadata.samples_read_count.clone())
+                .collect()
+        }),
+        sampling_metadatas.map(|metadatas| {
+            metadatas
+                .iter()
+                .map(|metadata| metadata.sampling_space_size.clone())
+                .collect()
+        }),
+    )
+}
+
+fn ga4_report_rows(rows: &[Ga4Row]) -> Vec<ReportRow> {
+    rows.iter()
+        .map(|row| ReportRow {
+            dimensions: row.dimensions(),
+            metrics: vec![row.date_range_value()],
+        })
+        .collect()
+}
+
+impl Ga4Report {
+    /// The report in Core Reporting v4 terms, with a single date range, so it can go
+    /// through the same flatteners.
+    pub fn to_report(&self) -> Report {
+        let (samples_read_counts, sampling_space_sizes) = ga4_sampling(self.metadata.as_ref());
+
+        Report {
+            column_header: ColumnHeader {
+                dimensions: ga4_dimension_names(&self.dimension_headers),
+                metric_header: MetricHeader {
+                    metric_header_entries: ga4_metric_header_entries(&self.metric_headers),
+                    pivot_headers: vec![],
+                },
+            },
+            data: ReportData {
+                rows: ga4_report_rows(&self.rows),
+                totals: ga4_summary(&self.totals).unwrap_or_default(),
+                row_count: self.row_count,
+                minimums: ga4_summary(&self.minimums),
+                maximums: ga4_summary(&self.maximums),
+                samples_read_counts,
+                sampling_space_sizes,
+                is_data_golden: None,
+            },
+            next_page_token: None,
+            source: ReportSource {
+                summaries_by_date_range: [&self.totals, &self.minimums, &self.maximums]
+                    .iter()
+                    .any(|summary| summary.len() > 1),
+                ..ReportSource::default()
+            },
+        }
+    }
+}
+
+impl Ga4PivotReport {
+    /// The report in Core Reporting v4 terms, so it can go through the same flatteners.
+    ///
+    /// In the wide layout, the first pivot's dimensions are the row dimensions and every
+    /// combination of the other pivots' dimension values gets a column per metric, named like
+    /// v4 pivot columns, e.g. `pivot1_Chrome_sessions`. Combinations without a row, which GA4
+    /// leaves out when it has no data for them, are left empty, as their ratios and averages
+    /// aren't zero. A row whose dimension values the pivot headers don't list, or that repeats
+    /// another row's, is an `UnexpectedPivotRow` error. In the long layout there is a row per
+    /// pivot cell, with every dimension.
+    ///
+    /// Aggregates are left out of both layouts: they are totals over some of the pivots'
+    /// dimensions, which have no place in a v4 report's single set of totals.
+    ///
+    /// Errors are located as if this were the only report in its response.
+    pub fn to_report(&self, layout: PivotLayout) -> Result<Report, FlattenError> {
+        self.indexed_report(0, layout)
+    }
+
+    fn indexed_report(
+        &self,
+        report_index: usize,
+        layout: PivotLayout,
+    ) -> Result<Report, FlattenError> {
+        let (column_header, rows, row_count) = match layout {
+            PivotLayout::Wide => self.wide_rows(report_index)?,
+            PivotLayout::Long => (
+                ColumnHeader {
+                    dimensions: ga4_dimension_names(&self.dimension_headers),
+                    metric_header: MetricHeader {
+                        metric_header_entries: ga4_metric_header_entries(&self.metric_headers),
+                        pivot_headers: vec![],
+                    },
+                },
+                ga4_report_rows(&self.rows),
+                None,
+            ),
+        };
+        let (samples_read_counts, sampling_space_sizes) = ga4_sampling(self.metadata.as_ref());
+
+        Ok(Report {
+            column_header,
+            data: ReportData {
+                rows,
+                totals: vec![],
+                row_count,
+                minimums: None,
+                maximums: None,
+                samples_read_counts,
+                sampling_space_sizes,
+                is_data_golden: None,
+            },
+            next_page_token: None,
+            source: ReportSource {
+                empty_metrics_are_missing: true,
+                ..ReportSource::default()
+            },
+        })
+    }
+
+    fn wide_rows(
+        &self,
+        report_index: usize,
+    ) -> Result<(ColumnHeader, Vec<ReportRow>, Option<u32>), FlattenError> {
+        let dimension_names = ga4_dimension_names(&self.dimension_headers);
+        let metric_entries = ga4_metric_header_entries(&self.metric_headers);
+
+        let (row_pivot, column_pivots) = match self.pivot_headers.split_first() {
+            Some(pivots) => pivots,
+            None => {
+                return Err(FlattenError::UnsupportedFeature(
+                    "pivot reports without pivot headers".to_string(),
+                ))
+            }
+        };
+        let pivot_dimensions: usize = self
+            .pivot_headers
+            .iter()
+            .map(Ga4PivotHeader::number_of_dimensions)
+            .sum();
+        if pivot_dimensions != dimension_names.len() {
+            return Err(FlattenError::UnsupportedFeature(format!(
+                "pivot headers with {} dimensions for {} dimension headers",
+                pivot_dimensions,
+                dimension_names.len()
+            )));
+        }
+        let row_dimensions = row_pivot.number_of_dimensions();
+
+        // every combination of the column pivots' values, the first pivot varying slowest
+        let column_keys: Vec<Vec<String>> =
+            column_pivots
+                .iter()
+                .fold(vec![vec![]], |keys: Vec<Vec<String>>, pivot| {
+                    keys.iter()
+                        .flat_map(|key| {
+                            pivot.combinations().into_iter().map(move |combination| {
+                                key.iter().chain(combination.iter()).cloned().collect()
+                            })
+                        })
+                        .collect()
+                });
+
+        let column_dimension_names = &dimension_names[row_dimensions..];
+        let pivot_header_entries = column_keys
+            .iter()
+            .flat_map(|key| {
+                metric_entries.iter().map(move |metric| PivotHeaderEntry {
+                    dimension_names: column_dimension_names.to_vec(),
+                    dimension_values: key.clone(),
+                    metric: metric.clone(),
+                })
+            })
+            .collect();
+
+        // each row must be a cell of the pivot table, or its values would have no column
+        // or overwrite another row's
+        let row_keys = row_pivot.combinations();
+        let listed_row_keys: HashSet<&[String]> = row_keys.iter().map(Vec::as_slice).collect();
+        let listed_column_keys: HashSet<&[String]> =
+            column_keys.iter().map(Vec::as_slice).collect();
+        let mut cells: HashMap<Vec<String>, Vec<String>> = HashMap::with_capacity(self.rows.len());
+        for (row_index, row) in self.rows.iter().enumerate() {
+            let location = Location {
+                report: report_index,
+                row: row_index,
+                column: None,
+            };
+            let dimensions = row.dimensions();
+            if dimensions.len() != dimension_names.len() {
+                return Err(FlattenError::ShapeMismatch {
+                    location,
+                    expected: dimension_names.len(),
+                    found: dimensions.len(),
+                });
+            }
+            let (row_key, column_key) = dimensions.split_at(row_dimensions);
+            if !listed_row_keys.contains(row_key) || !listed_column_keys.contains(column_key) {
+                return Err(FlattenError::UnexpectedPivotRow {
+                    location,
+                    duplicate: false,
+                });
+            }
+            if cells
+                .insert(dimensions, ga4_values(&row.metric_values))
+                .is_some()
+            {
+                return Err(FlattenError::UnexpectedPivotRow {
+                    location,
+                    duplicate: true,
+                });
+            }
+        }
+        // missing values are empty, which flattens to an empty cell
+        let missing = vec![String::new(); metric_entries.len()];
+
+        let rows = row_keys
+            .into_iter()
+            .map(|row_key| {
+                let values = column_keys
+                    .iter()
+                    .flat_map(|column_key| {
+                        let key: Vec<String> = row_key.iter().chain(column_key).cloned().collect();
+                        cells.get(&key).unwrap_or(&missing).clone()
+                    })
+                    .collect();
+
+                ReportRow {
+                    dimensions: row_key,
+                    metrics: vec![DateRangeValue {
+                        values: vec![],
+                        pivot_value_regions: vec![PivotValueRegion { values }],
+                    }],
+                }
+            })
+            .collect();
+
+        let column_header = ColumnHeader {
+            dimensions: dimension_names[..row_dimensions].to_vec(),
+            metric_header: MetricHeader {
+                metric_header_entries: vec![],
+                pivot_headers: vec![PivotHeader {
+                    pivot_header_entries,
+                    total_pivot_groups_count: None,
+                }],
+            },
+        };
+
+        Ok((column_header, rows, row_pivot.row_count))
+    }
+}
+
+// only says whether a response holds a batch of reports or pivot reports, so
+// it can then be parsed as exactly that and fail with whatever is wrong with it
+#[derive(Deserialize)]
+struct BatchKeys {
+    reports: Option<IgnoredAny>,
+    #[serde(rename = "pivotReports")]
+    pivot_reports: Option<IgnoredAny>,
+}
+
+// every field of a GA4 report is optional, but a report has at least a dimension
+// or a metric, which is what tells it apart from any other JSON object, like a
+// Core Reporting v4 report
+fn check_ga4_headers(
+    dimension_headers: &[Ga4DimensionHeader],
+    metric_headers: &[Ga4MetricHeader],
+) -> Result<(), FlattenError> {
+    if dimension_headers.is_empty() && metric_headers.is_empty() {
+        return Err(FlattenError::UnsupportedFeature(
+            "GA4 reports without dimensionHeaders or metricHeaders".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Reads a `runReport` or `batchRunReports` response as a `ReportResponse`.
+pub fn parse_ga4_response(raw_response: &str) -> Result<ReportResponse, FlattenError> {
+    let keys: BatchKeys = serde_json::from_str(raw_response)?;
+    let reports = match keys.reports {
+        Some(_) => serde_json::from_str::<Ga4BatchResponse>(raw_response)?.reports,
+        None => vec![serde_json::from_str::<Ga4Report>(raw_response)?],
+    };
+    for report in reports.iter() {
+        check_ga4_headers(&report.dimension_headers, &report.metric_headers)?;
+    }
+
+    Ok(ReportResponse {
+        reports: reports.iter().map(Ga4Report::to_report).collect(),
+    })
+}
+
+/// Reads a `runPivotReport` or `batchRunPivotReports` response as a `ReportResponse`, with
+/// the pivots laid out as described in `Ga4PivotReport::to_report`.
+pub fn parse_ga4_pivot_response(
+    raw_response: &str,
+    layout: PivotLayout,
+) -> Result<ReportResponse, FlattenError> {
+    let keys: BatchKeys = serde_json::from_str(raw_response)?;
+    let reports = match keys.pivot_reports {
+        Some(_) => serde_json::from_str::<Ga4BatchPivotResponse>(raw_response)?.pivot_reports,
+        None => vec![serde_json::from_str::<Ga4PivotReport>(raw_response)?],
+    };
+    for report in reports.iter() {
+        check_ga4_headers(&report.dimension_headers, &report.metric_headers)?;
+    }
+
+    Ok(ReportResponse {
+        reports: reports
+            .iter()
+            .enumerate()
+            .map(|(report_index, report)| report.indexed_report(report_index, layout))
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    fn read_response(name: &str) -> String {
+        fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("test_reports")
+                .join(name),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn run_report() {
+        let response = parse_ga4_response(&read_response("ga4_run_report.json")).unwrap();
+
+        assert_eq!(response.reports.len(), 1);
+        let report = &response.reports[0];
+        assert_eq!(
+            report.column_header.dimensions,
+            vec!["country", "deviceCategory"]
+        );
+        assert_eq!(
+            report.column_header.metric_header.metric_header_entries,
+            vec![
+                MetricHeaderEntry {
+                    name: "activeUsers".to_string(),
+                    metric_type: MetricType::Integer,
+                },
+                MetricHeaderEntry {
+                    name: "averageSessionDuration".to_string(),
+                    metric_type: MetricType::Time,
+                },
+            ]
+        );
+        assert_eq!(
+            report.data.rows[0].dimensions,
+            vec!["United States", "desktop"]
+        );
+        assert_eq!(report.data.rows[0].metrics[0].values, vec!["1032", "184.2"]);
+        assert_eq!(report.data.totals[0].values, vec!["1468", "171.35"]);
+        assert_eq!(report.data.row_count, Some(3));
+    }
+
+    #[test]
+    fn batch_run_reports() {
+        let response = parse_ga4_response(&read_response("ga4_batch_run_reports.json")).unwrap();
+
+        assert_eq!(response.reports.len(), 2);
+        assert_eq!(
+            response.reports[1].column_header.dimensions,
+            vec!["dateRange", "city"]
+        );
+        assert!(response.reports[1].data.totals.is_empty());
+        assert!(response.reports[1].source.summaries_by_date_range);
+        assert_eq!(
+            response.reports[1].metadata().samples_read_counts,
+            Some(vec!["2048".to_string()])
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_batches() {
+        let raw_response = r#"{
+            "reports": [{
+                "dimensionHeaders": [{"name": "country"}],
+                "metricHeaders": [{"name": "activeUsers", "type": "TYPE_INTEGER"}],
+                "rows": [{
+                    "dimensionValues": [{"value": "Canada"}],
+                    "metricValues": [{"value": 12}]
+                }]
+            }]
+        }"#;
+
+        match parse_ga4_response(raw_response) {
+            Err(FlattenError::Json(err)) => assert!(err
+                .to_string()
+                .starts_with("invalid type: integer `12`, expected a string")),
+            other => panic!("expected a JSON error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_v4_responses() {
+        let raw_response = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("test_reports/single_dimension_and_metric.json"),
+        )
+        .unwrap();
+
+        match parse_ga4_response(&raw_response) {
+            Err(FlattenError::UnsupportedFeature(feature)) => assert_eq!(
+                feature,
+                "GA4 reports without dimensionHeaders or metricHeaders"
+            ),
+            other => panic!("expected an unsupported feature error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn pivot_report_wide() {
+        let response =
+            parse_ga4_pivot_response(&read_response("ga4_pivot_report.json"), PivotLayout::Wide)
+                .unwrap();
+
+        let report = &response.reports[0];
+        assert_eq!(report.column_header.dimensions, vec!["country"]);
+        assert_eq!(
+            report
+                .get_metric_headers()
+                .iter()
+                .map(|entry| entry.name.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "pivot1_Chrome_sessions",
+                "pivot1_Chrome_engagementRate",
+                "pivot1_Safari_sessions",
+                "pivot1_Safari_engagementRate",
+            ]
+        );
+        assert_eq!(report.data.rows[1].dimensions, vec!["Canada"]);
+        assert_eq!(
+            report.data.rows[1]
+                .flat_value_iterator()
+                .collect::<Vec<_>>(),
+            vec!["62", "0.71", "", ""]
+        );
+        assert_eq!(report.data.row_count, Some(2));
+    }
+
+    #[test]
+    fn pivot_report_long() {
+        let response =
+            parse_ga4_pivot_response(&read_response("ga4_pivot_report.json"), PivotLayout::Long)
+                .unwrap();
+
+        let report = &response.reports[0];
+        assert_eq!(report.column_header.dimensions, vec!["country", "browser"]);
+        assert_eq!(report.data.rows.len(), 3);
+        assert_eq!(report.data.rows[2].dimensions, vec!["Canada", "Chrome"]);
+    }
+
+    #[test]
+    fn rejects_malformed_pivot_batches_and_v4_responses() {
+        let raw_response = r#"{
+            "pivotReports": [{
+                "pivotHeaders": [{"pivotDimensionHeaders": [{"dimensionValues": [{"value": "US"}]}]}],
+                "dimensionHeaders": [{"name": "country"}],
+                "metricHeaders": [{"name": "sessions", "type": "TYPE_INTEGER"}],
+                "rows": [{"dimensionValues": [{"value": "US"}], "metricValues": [{"value": 12}]}]
+            }]
+        }"#;
+
+        match parse_ga4_pivot_response(raw_response, PivotLayout::Long) {
+            Err(FlattenError::Json(err)) => assert!(err
+                .to_string()
+                .starts_with("invalid type: integer `12`, expected a string")),
+            other => panic!("expected a JSON error, got {:?}", other),
+        }
+
+        match parse_ga4_pivot_response(&read_response("pivot.json"), PivotLayout::Long) {
+            Err(FlattenError::UnsupportedFeature(feature)) => assert_eq!(
+                feature,
+                "GA4 reports without dimensionHeaders or metricHeaders"
+            ),
+            other => panic!("expected an unsupported feature error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_pivot_headers_not_matching_dimensions() {
+        let raw_response = r#"{
+            "pivotHeaders": [{"pivotDimensionHeaders": [{"dimensionValues": [{"value": "US"}]}]}],
+            "dimensionHeaders": [{"name": "country"}, {"name": "browser"}],
+            "metricHeaders": [{"name": "sessions", "type": "TYPE_INTEGER"}]
+        }"#;
+
+        match parse_ga4_pivot_response(raw_response, PivotLayout::Wide) {
+            Err(FlattenError::UnsupportedFeature(_)) => (),
+            other => panic!("expected an unsupported feature, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_pivot_rows_not_in_the_wide_layout() {
+        // a batch whose second report has rows for the given countries and browsers
+        let batch_with_rows = |rows: &[(&str, &str)]| {
+            let report = |rows: &[(&str, &str)]| {
+                json!({
+                    "pivotHeaders": [
+                        {"pivotDimensionHeaders": [{"dimensionValues": [{"value": "US"}]}]},
+                        {"pivotDimensionHeaders": [{"dimensionValues": [{"value": "Chrome"}]}]}
+                    ],
+                    "dimensionHeaders": [{"name": "country"}, {"name": "browser"}],
+                    "metricHeaders": [{"name": "sessions", "type": "TYPE_INTEGER"}],
+                    "rows": rows.iter().map(|(country, browser)| json!({
+                        "dimensionValues": [{"value": country}, {"value": browser}],
+                        "metricValues": [{"value": "12"}]
+                    })).collect::<Vec<_>>()
+                })
+            };
+
+            json!({"pivotReports": [report(&[("US", "Chrome")]), report(rows)]}).to_string()
+        };
+
+        assert!(parse_ga4_pivot_response(&batch_with_rows(&[]), PivotLayout::Wide).is_ok());
+        for (rows, expected_row, expected_duplicate) in [
+            (&[("US", "Safari")][..], 0, false),
+            (&[("Canada", "Chrome")][..], 0, false),
+            (&[("US", "Chrome"), ("US", "Chrome")][..], 1, true),
+        ] {
+            let batch = batch_with_rows(rows);
+            match parse_ga4_pivot_response(&batch, PivotLayout::Wide) {
+                Err(FlattenError::UnexpectedPivotRow {
+                    location,
+                    duplicate,
+                }) => {
+                    assert_eq!(location.report, 1);
+                    assert_eq!(location.row, expected_row);
+                    assert_eq!(duplicate, expected_duplicate);
+                }
+                other => panic!("expected an unexpected pivot row, got {:?}", other),
+            }
+            // the long layout has a row for every pivot row, whatever its values
+            assert!(parse_ga4_pivot_response(&batch, PivotLayout::Long).is_ok());
+        }
+    }
+
+    #[test]
+    fn unknown_metric_types_are_unspecified() {
+        let header: Ga4MetricHeader =
+            serde_json::from_str(r#"{"name": "eventValue", "type": "TYPE_LIGHT_YEARS"}"#).unwrap();
+
+        assert_eq!(
+            header.metric_type.to_metric_type(),
+            MetricType::MetricTypeUnspecified
+        );
+    }
+}
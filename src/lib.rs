@@ -9,54 +9,901 @@ extern crate serde_derive;
 #[macro_use]
 extern crate serde_json;
 
+pub mod comparison;
+pub mod error;
+pub mod flat_table;
+pub mod from_report;
+pub mod ga4;
+pub mod mcf;
+pub mod pages;
+pub mod report_row;
+pub mod request;
+pub mod streaming;
+#[cfg(feature = "arrow")]
+pub mod to_arrow;
 pub mod to_delimited;
+#[cfg(feature = "parquet")]
+pub mod to_parquet;
 pub mod to_row_array;
 pub mod types;
+pub mod user_activity;
+pub mod v3;
 
-use crate::to_delimited::response_to_delimited_reports;
-use crate::to_row_array::response_to_row_array;
+pub use crate::comparison::Comparison;
+pub use crate::error::{FlattenError, HeaderKind, HeaderMismatch, Location};
+pub use crate::flat_table::{Cell, Column, ColumnKind, FlatTable, FlatTableOptions};
+pub use crate::from_report::{from_report, from_report_with_options, FromReportOptions};
+pub use crate::ga4::{parse_ga4_pivot_response, parse_ga4_response, PivotLayout};
+pub use crate::mcf::{parse_mcf_response, PathFormat};
+pub use crate::pages::merge_pages;
+pub use crate::report_row::GaReportRow;
+pub use crate::request::{date_range_labels_from_request, DateRange};
+pub use crate::streaming::{stream_report_response, RowHandler};
+#[cfg(feature = "arrow")]
+pub use crate::to_arrow::{report_to_record_batch, write_ipc_file, write_ipc_stream};
+use crate::to_delimited::{
+    response_to_delimited_reports, write_delimited_reports, write_delimited_reports_from_reader,
+    write_delimited_reports_with,
+};
+pub use crate::to_delimited::{DelimitedOptions, LineTerminator, QuoteStyle};
+#[cfg(feature = "parquet")]
+pub use crate::to_parquet::{write_parquet_report, Codec, ParquetOptions};
+use crate::to_row_array::{response_to_row_array, write_json_lines_reports_from_reader};
+pub use crate::to_row_array::{FlatJsonOptions, TimeFormat};
 use crate::types::ReportResponse;
+pub use crate::types::{Layout, ReportMetadata};
+use crate::user_activity::{user_activity_to_row_array, write_user_activity, UserActivityResponse};
+pub use crate::v3::parse_v3_response;
+#[cfg(feature = "derive")]
+pub use ga_v4_flattener_derive::GaReportRow;
 use serde_json::value::Value;
-use serde_json::Error;
+use std::io;
 
-pub fn to_delimited(raw_report_response: &str, delimiter: &str) -> Result<Vec<String>, Error> {
+pub fn to_delimited(
+    raw_report_response: &str,
+    delimiter: &str,
+) -> Result<Vec<String>, FlattenError> {
+    to_delimited_with_options(
+        raw_report_response,
+        &DelimitedOptions {
+            delimiter: delimiter.to_string(),
+            ..Default::default()
+        },
+    )
+}
+
+pub fn to_delimited_with_options(
+    raw_report_response: &str,
+    options: &DelimitedOptions,
+) -> Result<Vec<String>, FlattenError> {
     if raw_report_response.is_empty() {
         return Ok(vec!["".to_string()]);
     }
 
     let deserialized_response: ReportResponse = serde_json::from_str(raw_report_response)?;
 
-    Ok(response_to_delimited_reports(
-        &deserialized_response,
-        &delimiter,
-    ))
+    response_to_delimited_reports(&deserialized_response, options)
+}
+
+pub fn write_delimited<W: io::Write>(
+    raw_report_response: &str,
+    writer: &mut W,
+    options: &DelimitedOptions,
+) -> Result<(), FlattenError> {
+    if raw_report_response.is_empty() {
+        return Ok(());
+    }
+
+    let deserialized_response: ReportResponse = serde_json::from_str(raw_report_response)?;
+
+    write_delimited_reports(&deserialized_response, writer, options)
+}
+
+pub fn write_delimited_per_report<W, F>(
+    raw_report_response: &str,
+    options: &DelimitedOptions,
+    writer_for_report: F,
+) -> Result<(), FlattenError>
+where
+    W: io::Write,
+    F: FnMut(usize) -> io::Result<W>,
+{
+    if raw_report_response.is_empty() {
+        return Ok(());
+    }
+
+    let deserialized_response: ReportResponse = serde_json::from_str(raw_report_response)?;
+
+    write_delimited_reports_with(&deserialized_response, options, writer_for_report)
+}
+
+/// Writes each report to its own Parquet file, in the writer `writer_for_report` returns for
+/// the report's index.
+#[cfg(feature = "parquet")]
+pub fn write_parquet_per_report<W, F>(
+    raw_report_response: &str,
+    options: &ParquetOptions,
+    writer_for_report: F,
+) -> Result<(), FlattenError>
+where
+    W: io::Write + Send,
+    F: FnMut(usize) -> io::Result<W>,
+{
+    if raw_report_response.is_empty() {
+        return Ok(());
+    }
+
+    let deserialized_response: ReportResponse = serde_json::from_str(raw_report_response)?;
+
+    to_parquet::write_parquet_reports(&deserialized_response, options, writer_for_report)
+}
+
+/// Like `to_delimited_with_options`, but names the date ranges after those in the request
+/// that produced the response, e.g. `ga:sessions[2024-01-01..2024-01-31]`. The request is
+/// anything accepted by `date_range_labels_from_request`.
+pub fn to_delimited_with_request(
+    raw_report_response: &str,
+    raw_request: &str,
+    options: &DelimitedOptions,
+) -> Result<Vec<String>, FlattenError> {
+    to_delimited_with_options(
+        raw_report_response,
+        &DelimitedOptions {
+            date_range_labels: date_range_labels_from_request(raw_request)?,
+            ..options.clone()
+        },
+    )
+}
+
+/// Merges the pages of a paginated response (see `merge_pages`) and converts each report
+/// to delimited data, as with `to_delimited_with_options`.
+pub fn to_delimited_from_pages<S: AsRef<str>>(
+    raw_pages: &[S],
+    options: &DelimitedOptions,
+) -> Result<Vec<String>, FlattenError> {
+    let merged_response = parse_pages(raw_pages)?;
+
+    response_to_delimited_reports(&merged_response, options)
+}
+
+/// Like `write_delimited`, but reads the response incrementally from `reader` and writes
+/// each row as soon as it has been parsed, so memory use doesn't grow with the number of rows.
+pub fn write_delimited_from_reader<R: io::Read, W: io::Write>(
+    reader: R,
+    writer: &mut W,
+    options: &DelimitedOptions,
+) -> Result<(), FlattenError> {
+    write_delimited_reports_from_reader(reader, writer, options)
 }
 
-pub fn to_flat_json(raw_report: &str) -> Result<Value, Error> {
+pub fn to_flat_json(raw_report: &str) -> Result<Value, FlattenError> {
     if raw_report.is_empty() {
         return Ok(json!("[]"));
     }
 
+    to_flat_json_with_options(raw_report, &FlatJsonOptions::default())
+}
+
+/// Writes the rows of every report as JSON Lines: each row is the object it would be in
+/// `to_flat_json_with_options`, on a line of its own. Set `include_report_index` to tell the
+/// reports apart.
+pub fn write_json_lines<W: io::Write>(
+    raw_report: &str,
+    writer: &mut W,
+    options: &FlatJsonOptions,
+) -> Result<(), FlattenError> {
+    write_json_lines_from_reader(raw_report.as_bytes(), writer, options)
+}
+
+/// Like `write_json_lines`, but reads the response incrementally from `reader` and writes
+/// each row as soon as it has been parsed, so memory use doesn't grow with the number of rows.
+pub fn write_json_lines_from_reader<R: io::Read, W: io::Write>(
+    reader: R,
+    writer: &mut W,
+    options: &FlatJsonOptions,
+) -> Result<(), FlattenError> {
+    write_json_lines_reports_from_reader(reader, writer, options)
+}
+
+/// Converts each report in the response to an Arrow `RecordBatch`, see
+/// `to_arrow::report_to_record_batch`.
+#[cfg(feature = "arrow")]
+pub fn to_record_batches(
+    raw_report_response: &str,
+) -> Result<Vec<arrow::record_batch::RecordBatch>, FlattenError> {
+    if raw_report_response.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let deserialized_response: ReportResponse = serde_json::from_str(raw_report_response)?;
+
+    to_arrow::response_to_record_batches(&deserialized_response)
+}
+
+/// Like `to_flat_json`, but each report is an object with its rows and metadata:
+/// `{"rows": [...], "metadata": {"isSampled": false, ...}}`.
+pub fn to_flat_json_with_metadata(raw_report: &str) -> Result<Value, FlattenError> {
+    to_flat_json_with_options(
+        raw_report,
+        &FlatJsonOptions {
+            include_metadata: true,
+            ..Default::default()
+        },
+    )
+}
+
+pub fn to_flat_json_with_options(
+    raw_report: &str,
+    options: &FlatJsonOptions,
+) -> Result<Value, FlattenError> {
+    if raw_report.is_empty() {
+        return Ok(json!([]));
+    }
+
     let deserialized_response: ReportResponse = serde_json::from_str(raw_report)?;
 
-    Ok(response_to_row_array(&deserialized_response))
+    response_to_row_array(&deserialized_response, options)
+}
+
+/// Like `to_flat_json_with_options`, but names the date ranges after those in the request
+/// that produced the response, as with `to_delimited_with_request`.
+pub fn to_flat_json_with_request(
+    raw_report: &str,
+    raw_request: &str,
+    options: &FlatJsonOptions,
+) -> Result<Value, FlattenError> {
+    to_flat_json_with_options(
+        raw_report,
+        &FlatJsonOptions {
+            date_range_labels: date_range_labels_from_request(raw_request)?,
+            ..options.clone()
+        },
+    )
+}
+
+/// Merges the pages of a paginated response (see `merge_pages`) and converts each report
+/// to flat JSON, as with `to_flat_json_with_options`.
+pub fn to_flat_json_from_pages<S: AsRef<str>>(
+    raw_pages: &[S],
+    options: &FlatJsonOptions,
+) -> Result<Value, FlattenError> {
+    let merged_response = parse_pages(raw_pages)?;
+
+    response_to_row_array(&merged_response, options)
+}
+
+/// Like `to_delimited_with_options`, for a GA4 Data API `runReport` or `batchRunReports`
+/// response. Dimension and metric headers are the GA4 names, e.g. `country` and `activeUsers`.
+pub fn ga4_to_delimited_with_options(
+    raw_response: &str,
+    options: &DelimitedOptions,
+) -> Result<Vec<String>, FlattenError> {
+    if raw_response.is_empty() {
+        return Ok(vec!["".to_string()]);
+    }
+
+    response_to_delimited_reports(&parse_ga4_response(raw_response)?, options)
+}
+
+/// Like `to_flat_json_with_options`, for a GA4 Data API `runReport` or `batchRunReports`
+/// response.
+pub fn ga4_to_flat_json_with_options(
+    raw_response: &str,
+    options: &FlatJsonOptions,
+) -> Result<Value, FlattenError> {
+    if raw_response.is_empty() {
+        return Ok(json!([]));
+    }
+
+    response_to_row_array(&parse_ga4_response(raw_response)?, options)
+}
+
+/// Like `ga4_to_delimited_with_options`, for a GA4 Data API `runPivotReport` or
+/// `batchRunPivotReports` response. `pivot_layout` chooses between a column per combination
+/// of the column pivots' values and a row per pivot cell, see `Ga4PivotReport::to_report`.
+pub fn ga4_pivot_to_delimited_with_options(
+    raw_response: &str,
+    pivot_layout: PivotLayout,
+    options: &DelimitedOptions,
+) -> Result<Vec<String>, FlattenError> {
+    if raw_response.is_empty() {
+        return Ok(vec!["".to_string()]);
+    }
+
+    response_to_delimited_reports(
+        &parse_ga4_pivot_response(raw_response, pivot_layout)?,
+        options,
+    )
+}
+
+/// Like `ga4_to_flat_json_with_options`, for a GA4 Data API `runPivotReport` or
+/// `batchRunPivotReports` response, as with `ga4_pivot_to_delimited_with_options`.
+pub fn ga4_pivot_to_flat_json_with_options(
+    raw_response: &str,
+    pivot_layout: PivotLayout,
+    options: &FlatJsonOptions,
+) -> Result<Value, FlattenError> {
+    if raw_response.is_empty() {
+        return Ok(json!([]));
+    }
+
+    response_to_row_array(
+        &parse_ga4_pivot_response(raw_response, pivot_layout)?,
+        options,
+    )
+}
+
+/// Like `to_delimited_with_options`, for a Core Reporting API v3 `data.ga.get` response.
+pub fn v3_to_delimited_with_options(
+    raw_response: &str,
+    options: &DelimitedOptions,
+) -> Result<Vec<String>, FlattenError> {
+    if raw_response.is_empty() {
+        return Ok(vec!["".to_string()]);
+    }
+
+    response_to_delimited_reports(&parse_v3_response(raw_response)?, options)
+}
+
+/// Like `to_flat_json_with_options`, for a Core Reporting API v3 `data.ga.get` response.
+pub fn v3_to_flat_json_with_options(
+    raw_response: &str,
+    options: &FlatJsonOptions,
+) -> Result<Value, FlattenError> {
+    if raw_response.is_empty() {
+        return Ok(json!([]));
+    }
+
+    response_to_row_array(&parse_v3_response(raw_response)?, options)
+}
+
+/// Like `to_delimited_with_options`, for a Multi-Channel Funnels `data.mcf.get` response,
+/// with conversion paths flattened as chosen by `path_format`.
+pub fn mcf_to_delimited_with_options(
+    raw_response: &str,
+    path_format: PathFormat,
+    options: &DelimitedOptions,
+) -> Result<Vec<String>, FlattenError> {
+    if raw_response.is_empty() {
+        return Ok(vec!["".to_string()]);
+    }
+
+    response_to_delimited_reports(&parse_mcf_response(raw_response, path_format)?, options)
+}
+
+/// Like `to_flat_json_with_options`, for a Multi-Channel Funnels `data.mcf.get` response,
+/// with conversion paths flattened as chosen by `path_format`.
+pub fn mcf_to_flat_json_with_options(
+    raw_response: &str,
+    path_format: PathFormat,
+    options: &FlatJsonOptions,
+) -> Result<Value, FlattenError> {
+    if raw_response.is_empty() {
+        return Ok(json!([]));
+    }
+
+    response_to_row_array(&parse_mcf_response(raw_response, path_format)?, options)
+}
+
+/// Flattens a `userActivity:search` response to delimited data with a line per activity,
+/// see `USER_ACTIVITY_COLUMNS` for the columns. An activity's goals and products share its
+/// line, with their names and SKUs joined and their completions and values summed, so the
+/// details of each goal and product are lost.
+pub fn user_activity_to_delimited(
+    raw_response: &str,
+    options: &DelimitedOptions,
+) -> Result<Vec<String>, FlattenError> {
+    if raw_response.is_empty() {
+        return Ok(vec!["".to_string()]);
+    }
+
+    let deserialized_response: UserActivityResponse = serde_json::from_str(raw_response)?;
+    let mut result: Vec<u8> = Vec::new();
+    write_user_activity(&deserialized_response, &mut result, options)?;
+
+    Ok(vec![
+        String::from_utf8(result).expect("delimited output is only written from strings")
+    ])
+}
+
+/// Flattens a `userActivity:search` response to an array with an object per activity, with
+/// goals and products combined as in `user_activity_to_delimited`.
+pub fn user_activity_to_flat_json(raw_response: &str) -> Result<Value, FlattenError> {
+    if raw_response.is_empty() {
+        return Ok(json!([]));
+    }
+
+    let deserialized_response: UserActivityResponse = serde_json::from_str(raw_response)?;
+
+    user_activity_to_row_array(&deserialized_response)
+}
+
+fn parse_pages<S: AsRef<str>>(raw_pages: &[S]) -> Result<ReportResponse, FlattenError> {
+    let pages = raw_pages
+        .iter()
+        .map(|raw_page| serde_json::from_str(raw_page.as_ref()))
+        .collect::<Result<Vec<ReportResponse>, serde_json::Error>>()?;
+
+    merge_pages(pages)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::to_delimited;
+    use super::*;
     use std::fs;
     use std::path::PathBuf;
 
     #[test]
-    fn rejects_reports_containing_unsupported_features() {
+    fn accepts_reports_containing_pivots() {
         let data: String = fs::read_to_string(PathBuf::from(format!(
             "{}{}",
             env!("CARGO_MANIFEST_DIR"),
-            "/test_reports/unsupported_feature.json"
+            "/test_reports/pivot.json"
         )))
         .unwrap();
 
-        assert!(to_delimited(&data, ",").is_err())
+        assert!(to_delimited(&data, ",").is_ok())
+    }
+
+    #[test]
+    fn pivots_sharing_values_and_a_metric_get_distinct_columns() {
+        let data: String = fs::read_to_string(PathBuf::from(format!(
+            "{}{}",
+            env!("CARGO_MANIFEST_DIR"),
+            "/test_reports/two_pivots.json"
+        )))
+        .unwrap();
+
+        assert_eq!(
+            to_delimited(&data, ",").unwrap(),
+            vec![indoc!(
+                r#"
+                "ga:deviceCategory","ga:users","pivot1_201831_ga:sessions","pivot2_201831_ga:sessions"
+                "desktop",38,43,41
+                "#
+            )]
+        );
+        assert_eq!(
+            to_flat_json(&data).unwrap(),
+            json!([[{
+                "ga:deviceCategory": "desktop",
+                "ga:users": 38,
+                "pivot1_201831_ga:sessions": 43,
+                "pivot2_201831_ga:sessions": 41,
+            }]])
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_json() {
+        match to_flat_json("{\"reports\": [") {
+            Err(FlattenError::Json(_)) => (),
+            other => panic!("expected a JSON error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn empty_input_is_an_empty_array() {
+        let options = FlatJsonOptions::default();
+
+        assert_eq!(to_flat_json_with_options("", &options).unwrap(), json!([]));
+        assert_eq!(
+            ga4_to_flat_json_with_options("", &options).unwrap(),
+            json!([])
+        );
+        assert_eq!(
+            ga4_pivot_to_flat_json_with_options("", PivotLayout::Wide, &options).unwrap(),
+            json!([])
+        );
+        assert_eq!(
+            v3_to_flat_json_with_options("", &options).unwrap(),
+            json!([])
+        );
+        assert_eq!(
+            mcf_to_flat_json_with_options("", PathFormat::Joined, &options).unwrap(),
+            json!([])
+        );
+        assert_eq!(user_activity_to_flat_json("").unwrap(), json!([]));
+    }
+
+    #[test]
+    fn rejects_rows_missing_dimensions() {
+        let data: String = fs::read_to_string(PathBuf::from(format!(
+            "{}{}",
+            env!("CARGO_MANIFEST_DIR"),
+            "/test_reports/missing_dimension.json"
+        )))
+        .unwrap();
+
+        let expected_location = Location {
+            report: 0,
+            row: 1,
+            column: Some("ga:country".to_string()),
+        };
+
+        match to_flat_json(&data) {
+            Err(FlattenError::ShapeMismatch {
+                location,
+                expected: 2,
+                found: 1,
+            }) => assert_eq!(location, expected_location),
+            other => panic!("expected a shape mismatch, got {:?}", other),
+        }
+
+        match to_delimited(&data, ",") {
+            Err(FlattenError::ShapeMismatch { location, .. }) => {
+                assert_eq!(location, expected_location)
+            }
+            other => panic!("expected a shape mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_metric_values() {
+        let data: String = fs::read_to_string(PathBuf::from(format!(
+            "{}{}",
+            env!("CARGO_MANIFEST_DIR"),
+            "/test_reports/invalid_metric_value.json"
+        )))
+        .unwrap();
+
+        match to_flat_json(&data) {
+            Err(FlattenError::InvalidMetricValue { location, value }) => {
+                assert_eq!(
+                    location,
+                    Location {
+                        report: 0,
+                        row: 1,
+                        column: Some("ga:sessions".to_string()),
+                    }
+                );
+                assert_eq!(value, "n/a");
+            }
+            other => panic!("expected an invalid metric value, got {:?}", other),
+        }
+
+        match to_delimited(&data, ",") {
+            Err(FlattenError::InvalidMetricValue { value, .. }) => assert_eq!(value, "n/a"),
+            other => panic!("expected an invalid metric value, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_quote_char_in_delimiter() {
+        let data: String = fs::read_to_string(PathBuf::from(format!(
+            "{}{}",
+            env!("CARGO_MANIFEST_DIR"),
+            "/test_reports/single_dimension_and_metric.json"
+        )))
+        .unwrap();
+
+        match to_delimited(&data, "\"") {
+            Err(FlattenError::UnsupportedFeature(_)) => (),
+            other => panic!("expected an unsupported feature, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn writes_json_lines() {
+        let report = fs::read_to_string(PathBuf::from(format!(
+            "{}/test_reports/multiple_reports.json",
+            env!("CARGO_MANIFEST_DIR")
+        )))
+        .unwrap();
+        let options = FlatJsonOptions {
+            include_report_index: true,
+            ..Default::default()
+        };
+
+        let mut output: Vec<u8> = Vec::new();
+        write_json_lines(&report, &mut output, &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let expected: Vec<String> = to_flat_json_with_options(&report, &options)
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .iter()
+            .flat_map(|rows| rows.as_array().unwrap().iter().map(Value::to_string))
+            .collect();
+        assert_eq!(output.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn labels_date_ranges_from_request() {
+        let data: String = fs::read_to_string(PathBuf::from(format!(
+            "{}{}",
+            env!("CARGO_MANIFEST_DIR"),
+            "/test_reports/multiple_date_ranges.json"
+        )))
+        .unwrap();
+        let request = r#"{"reportRequests": [{"dateRanges": [
+            {"startDate": "2024-02-01", "endDate": "2024-02-29"},
+            {"startDate": "2024-01-01", "endDate": "2024-01-31"}
+        ]}]}"#;
+
+        assert_eq!(
+            to_delimited_with_request(&data, request, &DelimitedOptions::default()).unwrap(),
+            vec![indoc!(
+                r#""ga:browser","ga:avgTimeOnPage[2024-02-01..2024-02-29]","ga:pageviewsPerSession[2024-02-01..2024-02-29]","ga:avgTimeOnPage[2024-01-01..2024-01-31]","ga:pageviewsPerSession[2024-01-01..2024-01-31]"
+                    "Chrome",108.1733,2.93126,129.7071651,3.60975609
+                    "Edge",51.794117,6.6666667,210.866667,2.875
+                    "Firefox",123.657142,2.09375,75.333333,1.5
+                    "#
+            )
+            .to_string()]
+        );
+
+        let rows = to_flat_json_with_request(&data, request, &FlatJsonOptions::default()).unwrap();
+        assert_eq!(
+            rows[0][0],
+            json!({
+                "ga:browser": "Chrome",
+                "ga:avgTimeOnPage[2024-02-01..2024-02-29]": 108.1733,
+                "ga:pageviewsPerSession[2024-02-01..2024-02-29]": 2.93126,
+                "ga:avgTimeOnPage[2024-01-01..2024-01-31]": 129.7071651,
+                "ga:pageviewsPerSession[2024-01-01..2024-01-31]": 3.60975609,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_number_of_date_range_labels() {
+        let data: String = fs::read_to_string(PathBuf::from(format!(
+            "{}{}",
+            env!("CARGO_MANIFEST_DIR"),
+            "/test_reports/multiple_date_ranges.json"
+        )))
+        .unwrap();
+        let request = r#"[{"startDate": "2024-01-01", "endDate": "2024-01-31"}]"#;
+
+        match to_delimited_with_request(&data, request, &DelimitedOptions::default()) {
+            Err(FlattenError::DateRangeMismatch {
+                report: 0,
+                expected: 2,
+                found: 1,
+            }) => (),
+            other => panic!("expected a date range mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn flattens_ga4_reports() {
+        let data: String = fs::read_to_string(PathBuf::from(format!(
+            "{}{}",
+            env!("CARGO_MANIFEST_DIR"),
+            "/test_reports/ga4_run_report.json"
+        )))
+        .unwrap();
+
+        let options = DelimitedOptions {
+            include_totals: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            ga4_to_delimited_with_options(&data, &options).unwrap(),
+            vec![indoc!(
+                r#""country","deviceCategory","activeUsers","averageSessionDuration"
+                    "United States","desktop",1032,184.2
+                    "United States","mobile",391,139.75
+                    "Canada","desktop",45,161.5
+                    "TOTAL","",1468,171.35
+                    "#
+            )
+            .to_string()]
+        );
+        assert_eq!(
+            ga4_to_flat_json_with_options(&data, &FlatJsonOptions::default()).unwrap()[0][2],
+            json!({
+                "country": "Canada",
+                "deviceCategory": "desktop",
+                "activeUsers": 45,
+                "averageSessionDuration": 161.5,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_ga4_summaries_of_several_date_ranges() {
+        let data: String = fs::read_to_string(PathBuf::from(format!(
+            "{}{}",
+            env!("CARGO_MANIFEST_DIR"),
+            "/test_reports/ga4_batch_run_reports.json"
+        )))
+        .unwrap();
+
+        assert_eq!(
+            ga4_to_delimited_with_options(&data, &DelimitedOptions::default())
+                .unwrap()
+                .len(),
+            2
+        );
+        let expected = "summaries of report 1, which has one for each of its date ranges";
+        match ga4_to_delimited_with_options(
+            &data,
+            &DelimitedOptions {
+                include_totals: true,
+                ..Default::default()
+            },
+        ) {
+            Err(FlattenError::UnsupportedFeature(feature)) => assert_eq!(feature, expected),
+            other => panic!("expected an unsupported feature error, got {:?}", other),
+        }
+        match ga4_to_flat_json_with_options(
+            &data,
+            &FlatJsonOptions {
+                include_maximums: true,
+                ..Default::default()
+            },
+        ) {
+            Err(FlattenError::UnsupportedFeature(feature)) => assert_eq!(feature, expected),
+            other => panic!("expected an unsupported feature error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn flattens_ga4_pivot_reports() {
+        let data: String = fs::read_to_string(PathBuf::from(format!(
+            "{}{}",
+            env!("CARGO_MANIFEST_DIR"),
+            "/test_reports/ga4_pivot_report.json"
+        )))
+        .unwrap();
+
+        assert_eq!(
+            ga4_pivot_to_delimited_with_options(&data, PivotLayout::Wide, &DelimitedOptions::default())
+                .unwrap(),
+            vec![indoc!(
+                r#""country","pivot1_Chrome_sessions","pivot1_Chrome_engagementRate","pivot1_Safari_sessions","pivot1_Safari_engagementRate"
+                    "United States",812,0.64,307,0.58
+                    "Canada",62,0.71,,
+                    "#
+            )
+            .to_string()]
+        );
+        assert_eq!(
+            ga4_pivot_to_flat_json_with_options(
+                &data,
+                PivotLayout::Wide,
+                &FlatJsonOptions::default()
+            )
+            .unwrap()[0][1],
+            json!({
+                "country": "Canada",
+                "pivot1_Chrome_sessions": 62,
+                "pivot1_Chrome_engagementRate": 0.71,
+                "pivot1_Safari_sessions": null,
+                "pivot1_Safari_engagementRate": null,
+            })
+        );
+        assert_eq!(
+            ga4_pivot_to_flat_json_with_options(
+                &data,
+                PivotLayout::Long,
+                &FlatJsonOptions::default()
+            )
+            .unwrap(),
+            json!([[
+                {"country": "United States", "browser": "Chrome", "sessions": 812, "engagementRate": 0.64},
+                {"country": "United States", "browser": "Safari", "sessions": 307, "engagementRate": 0.58},
+                {"country": "Canada", "browser": "Chrome", "sessions": 62, "engagementRate": 0.71},
+            ]])
+        );
+    }
+
+    #[test]
+    fn flattens_v3_responses() {
+        let data: String = fs::read_to_string(PathBuf::from(format!(
+            "{}{}",
+            env!("CARGO_MANIFEST_DIR"),
+            "/test_reports/v3_data_ga.json"
+        )))
+        .unwrap();
+
+        let options = DelimitedOptions {
+            include_totals: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            v3_to_delimited_with_options(&data, &options).unwrap(),
+            vec![indoc!(
+                r#""ga:deviceCategory","ga:country","ga:sessions","ga:bounceRate"
+                    "desktop","Canada",43,51.16
+                    "mobile","Canada",1,100.0
+                    "tablet","United States",6,50.0
+                    "TOTAL","",50,52.0
+                    "#
+            )
+            .to_string()]
+        );
+        assert_eq!(
+            v3_to_flat_json_with_options(&data, &FlatJsonOptions::default()).unwrap()[0][2],
+            json!({
+                "ga:deviceCategory": "tablet",
+                "ga:country": "United States",
+                "ga:sessions": 6,
+                "ga:bounceRate": 50.0,
+            })
+        );
+    }
+
+    #[test]
+    fn flattens_mcf_responses() {
+        let data: String = fs::read_to_string(PathBuf::from(format!(
+            "{}{}",
+            env!("CARGO_MANIFEST_DIR"),
+            "/test_reports/mcf_data.json"
+        )))
+        .unwrap();
+
+        assert_eq!(
+            mcf_to_delimited_with_options(&data, PathFormat::Joined, &DelimitedOptions::default())
+                .unwrap(),
+            vec![indoc!(
+                r#""mcf:basicChannelGroupingPath","mcf:totalConversions","mcf:totalConversionValue"
+                    "Direct > Organic Search > Email",12,840.5
+                    "Referral",3,180.0
+                    "#
+            )
+            .to_string()]
+        );
+        assert_eq!(
+            mcf_to_flat_json_with_options(&data, PathFormat::Exploded, &FlatJsonOptions::default())
+                .unwrap(),
+            json!([[
+                {"mcf:basicChannelGroupingPath": "Direct", "step": 1, "mcf:totalConversions": 12, "mcf:totalConversionValue": 840.5},
+                {"mcf:basicChannelGroupingPath": "Organic Search", "step": 2, "mcf:totalConversions": 12, "mcf:totalConversionValue": 840.5},
+                {"mcf:basicChannelGroupingPath": "Email", "step": 3, "mcf:totalConversions": 12, "mcf:totalConversionValue": 840.5},
+                {"mcf:basicChannelGroupingPath": "Referral", "step": 1, "mcf:totalConversions": 3, "mcf:totalConversionValue": 180.0},
+            ]])
+        );
+    }
+
+    #[test]
+    fn flattens_user_activity() {
+        let data: String = fs::read_to_string(PathBuf::from(format!(
+            "{}{}",
+            env!("CARGO_MANIFEST_DIR"),
+            "/test_reports/user_activity.json"
+        )))
+        .unwrap();
+
+        let delimited = user_activity_to_delimited(&data, &DelimitedOptions::default()).unwrap();
+        assert_eq!(delimited.len(), 1);
+        assert_eq!(delimited[0].lines().count(), 5);
+        assert_eq!(
+            user_activity_to_flat_json(&data).unwrap()[3]["productSkus"],
+            json!("SKU-1, SKU-2")
+        );
+    }
+
+    #[test]
+    fn merges_pages() {
+        let pages: Vec<String> = ["paginated_page_1.json", "paginated_page_2.json"]
+            .iter()
+            .map(|name| {
+                fs::read_to_string(PathBuf::from(format!(
+                    "{}/test_reports/{}",
+                    env!("CARGO_MANIFEST_DIR"),
+                    name
+                )))
+                .unwrap()
+            })
+            .collect();
+
+        assert_eq!(
+            to_delimited_from_pages(&pages, &DelimitedOptions::default()).unwrap(),
+            vec!["\"ga:deviceCategory\",\"ga:sessions\"\n\"desktop\",43\n\"mobile\",1\n\"tablet\",6\n"]
+        );
+        assert_eq!(
+            to_flat_json_from_pages(&pages, &FlatJsonOptions::default()).unwrap(),
+            json!([[
+                {"ga:deviceCategory": "desktop", "ga:sessions": 43},
+                {"ga:deviceCategory": "mobile", "ga:sessions": 1},
+                {"ga:deviceCategory": "tablet", "ga:sessions": 6},
+            ]])
+        );
     }
 }
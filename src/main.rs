@@ -0,0 +1,625 @@
+use ga_v4_flattener::to_delimited::{write_delimited_reports, write_delimited_reports_with};
+use ga_v4_flattener::to_row_array::{response_to_row_array, write_json_lines_reports};
+use ga_v4_flattener::types::ReportResponse;
+use ga_v4_flattener::user_activity::{
+    user_activity_to_row_array, write_user_activity, UserActivityResponse,
+};
+use ga_v4_flattener::{
+    parse_ga4_pivot_response, parse_ga4_response, parse_mcf_response, parse_v3_response,
+    write_delimited_from_reader, write_json_lines_from_reader, Comparison, DelimitedOptions,
+    FlatJsonOptions, FlattenError, Layout, LineTerminator, PathFormat, PivotLayout, QuoteStyle,
+    TimeFormat,
+};
+use serde_json::Value;
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process;
+
+const USAGE: &str = "\
+Converts Google Analytics API responses, from Reporting API v4 unless --source says
+otherwise, to CSV, TSV, flat JSON or JSON Lines.
+
+Usage: ga-v4-flattener [OPTIONS] [INPUT]
+
+Reads the response from INPUT, or from stdin when INPUT is missing or `-`, and writes
+every report to stdout, or to a file per report with --output-dir. A user activity
+response is a single table of activities, which the date range and summary options
+don't apply to.
+
+Options:
+  -s, --source <v4|ga4|ga4-pivot|v3|mcf|user-activity>
+                                  The API the response is from [default: v4]
+  -f, --format <csv|tsv|json|jsonl>
+                                  Output format [default: csv]
+  -d, --delimiter <DELIMITER>     Field delimiter for delimited output [default: , for csv, tab for tsv]
+  -o, --output-dir <DIR>          Write each report to its own file in DIR
+  -t, --filename-template <NAME>  File name for each report in --output-dir, where {index} is
+                                  the report's index and {ext} the format [default: report_{index}.{ext}]
+      --quote-style <always|necessary>
+                                  When to quote headers and dimensions [default: always]
+      --crlf                      End lines with \\r\\n
+      --no-header                 Leave out the header line
+      --bom                       Start the output, or each report's file, with a UTF-8
+                                  byte order mark
+      --layout <wide|long>        How multiple date ranges are flattened [default: wide]
+      --totals                    Include each report's totals (not for jsonl)
+      --minimums                  Include each report's minimums (not for jsonl)
+      --maximums                  Include each report's maximums (not for jsonl)
+      --metadata                  Include each report's sampling and paging metadata
+                                  (not for jsonl)
+      --compare <BASE>            Compare each date range with date range BASE, counted
+                                  from 0 (wide layout only)
+      --time-format <seconds|iso8601>
+                                  How TIME metrics are written in JSON [default: seconds]
+      --pivot-layout <wide|long>  How ga4-pivot pivots are flattened [default: wide]
+      --paths <joined|exploded>   How mcf conversion paths are flattened [default: joined]
+  -h, --help                      Print this help
+";
+
+const DEFAULT_FILENAME_TEMPLATE: &str = "report_{index}.{ext}";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    V4,
+    Ga4,
+    Ga4Pivot,
+    V3,
+    Mcf,
+    UserActivity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Csv,
+    Tsv,
+    Json,
+    JsonLines,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Tsv => "tsv",
+            Format::Json => "json",
+            Format::JsonLines => "jsonl",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Args {
+    input: Option<PathBuf>,
+    source: Source,
+    pivot_layout: PivotLayout,
+    path_format: PathFormat,
+    format: Format,
+    output_dir: Option<PathBuf>,
+    filename_template: String,
+    delimited_options: DelimitedOptions,
+    flat_json_options: FlatJsonOptions,
+}
+
+impl Args {
+    fn filename(&self, report_index: usize) -> String {
+        self.filename_template
+            .replace("{index}", &report_index.to_string())
+            .replace("{ext}", self.format.extension())
+    }
+}
+
+#[derive(Debug)]
+enum CliError {
+    Help,
+    Usage(String),
+    Io(String, io::Error),
+    Flatten(FlattenError),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Help => write!(f, "{}", USAGE),
+            CliError::Usage(message) => write!(f, "{}\n\n{}", message, USAGE),
+            CliError::Io(action, err) => write!(f, "failed to {}: {}", action, err),
+            CliError::Flatten(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<FlattenError> for CliError {
+    fn from(err: FlattenError) -> Self {
+        CliError::Flatten(err)
+    }
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Args, CliError> {
+    let mut input = None;
+    let mut input_given = false;
+    let mut source = Source::V4;
+    let mut pivot_layout = PivotLayout::Wide;
+    let mut path_format = PathFormat::Joined;
+    let mut format = Format::Csv;
+    let mut delimiter = None;
+    let mut output_dir = None;
+    let mut filename_template = DEFAULT_FILENAME_TEMPLATE.to_string();
+    let mut delimited_options = DelimitedOptions::default();
+    let mut flat_json_options = FlatJsonOptions::default();
+
+    while let Some(arg) = args.next() {
+        let mut value_for = |flag: &str| {
+            args.next()
+                .ok_or_else(|| CliError::Usage(format!("{} needs a value", flag)))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Err(CliError::Help),
+            "-s" | "--source" => {
+                source = match value_for(&arg)?.as_str() {
+                    "v4" => Source::V4,
+                    "ga4" => Source::Ga4,
+                    "ga4-pivot" => Source::Ga4Pivot,
+                    "v3" => Source::V3,
+                    "mcf" => Source::Mcf,
+                    "user-activity" => Source::UserActivity,
+                    other => return Err(CliError::Usage(format!("unknown source {:?}", other))),
+                }
+            }
+            "-f" | "--format" => {
+                format = match value_for(&arg)?.as_str() {
+                    "csv" => Format::Csv,
+                    "tsv" => Format::Tsv,
+                    "json" => Format::Json,
+                    "jsonl" => Format::JsonLines,
+                    other => return Err(CliError::Usage(format!("unknown format {:?}", other))),
+                }
+            }
+            "-d" | "--delimiter" => delimiter = Some(value_for(&arg)?),
+            "-o" | "--output-dir" => output_dir = Some(PathBuf::from(value_for(&arg)?)),
+            "-t" | "--filename-template" => filename_template = value_for(&arg)?,
+            "--quote-style" => {
+                delimited_options.quote_style = match value_for(&arg)?.as_str() {
+                    "always" => QuoteStyle::Always,
+                    "necessary" => QuoteStyle::Necessary,
+                    other => {
+                        return Err(CliError::Usage(format!("unknown quote style {:?}", other)))
+                    }
+                }
+            }
+            "--layout" => {
+                let layout = match value_for(&arg)?.as_str() {
+                    "wide" => Layout::Wide,
+                    "long" => Layout::Long,
+                    other => return Err(CliError::Usage(format!("unknown layout {:?}", other))),
+                };
+                delimited_options.layout = layout;
+                flat_json_options.layout = layout;
+            }
+            "--compare" => {
+                let base = value_for(&arg)?;
+                let comparison = Comparison {
+                    base_date_range: base.parse().map_err(|_| {
+                        CliError::Usage(format!(
+                            "--compare needs a date range index, got {:?}",
+                            base
+                        ))
+                    })?,
+                };
+                delimited_options.comparison = Some(comparison);
+                flat_json_options.comparison = Some(comparison);
+            }
+            "--time-format" => {
+                flat_json_options.time_format = match value_for(&arg)?.as_str() {
+                    "seconds" => TimeFormat::Seconds,
+                    "iso8601" => TimeFormat::Iso8601Duration,
+                    other => {
+                        return Err(CliError::Usage(format!("unknown time format {:?}", other)))
+                    }
+                }
+            }
+            "--pivot-layout" => {
+                pivot_layout = match value_for(&arg)?.as_str() {
+                    "wide" => PivotLayout::Wide,
+                    "long" => PivotLayout::Long,
+                    other => {
+                        return Err(CliError::Usage(format!("unknown pivot layout {:?}", other)))
+                    }
+                }
+            }
+            "--paths" => {
+                path_format = match value_for(&arg)?.as_str() {
+                    "joined" => PathFormat::Joined,
+                    "exploded" => PathFormat::Exploded,
+                    other => {
+                        return Err(CliError::Usage(format!("unknown path format {:?}", other)))
+                    }
+                }
+            }
+            "--crlf" => delimited_options.line_terminator = LineTerminator::CrLf,
+            "--no-header" => delimited_options.include_header = false,
+            "--bom" => delimited_options.byte_order_mark = true,
+            "--totals" => {
+                delimited_options.include_totals = true;
+                flat_json_options.include_totals = true;
+            }
+            "--minimums" => {
+                delimited_options.include_minimums = true;
+                flat_json_options.include_minimums = true;
+            }
+            "--maximums" => {
+                delimited_options.include_maximums = true;
+                flat_json_options.include_maximums = true;
+            }
+            "--metadata" => {
+                delimited_options.include_metadata_comments = true;
+                flat_json_options.include_metadata = true;
+            }
+            flag if flag.starts_with('-') && flag != "-" => {
+                return Err(CliError::Usage(format!("unknown option {}", flag)))
+            }
+            extra if input_given => {
+                return Err(CliError::Usage(format!(
+                    "unexpected argument {}, only one INPUT can be given",
+                    extra
+                )))
+            }
+            path => {
+                input_given = true;
+                input = Some(PathBuf::from(path)).filter(|path| path.as_os_str() != "-");
+            }
+        }
+    }
+
+    // JSON Lines has a line per row and nowhere to put summaries or metadata
+    let json_options = &flat_json_options;
+    if format == Format::JsonLines
+        && (json_options.include_totals
+            || json_options.include_minimums
+            || json_options.include_maximums
+            || json_options.include_metadata)
+    {
+        return Err(CliError::Usage(
+            "--totals, --minimums, --maximums and --metadata can't be used with --format jsonl"
+                .to_string(),
+        ));
+    }
+
+    delimited_options.delimiter = match (delimiter, format) {
+        (Some(delimiter), _) => delimiter,
+        (None, Format::Tsv) => "\t".to_string(),
+        (None, _) => ",".to_string(),
+    };
+
+    Ok(Args {
+        input,
+        source,
+        pivot_layout,
+        path_format,
+        format,
+        output_dir,
+        filename_template,
+        delimited_options,
+        flat_json_options,
+    })
+}
+
+fn open_input(args: &Args) -> Result<Box<dyn Read>, CliError> {
+    match &args.input {
+        Some(path) => File::open(path)
+            .map(|file| Box::new(file) as Box<dyn Read>)
+            .map_err(|err| CliError::Io(format!("open {}", path.display()), err)),
+        None => Ok(Box::new(io::stdin())),
+    }
+}
+
+fn read_input(args: &Args) -> Result<String, CliError> {
+    let mut raw_response = String::new();
+    open_input(args)?
+        .read_to_string(&mut raw_response)
+        .map_err(|err| CliError::Io("read the response".to_string(), err))?;
+
+    Ok(raw_response)
+}
+
+fn create_output(args: &Args, report_index: usize) -> io::Result<File> {
+    let output_dir = args
+        .output_dir
+        .as_ref()
+        .expect("files are only created with an output directory");
+
+    File::create(output_dir.join(args.filename(report_index)))
+}
+
+fn write_rows<W: Write>(writer: &mut W, report: &Value) -> io::Result<()> {
+    for row in report.as_array().into_iter().flatten() {
+        writeln!(writer, "{}", row)?;
+    }
+
+    Ok(())
+}
+
+// each report is an array, or an object with its rows and summaries, like the
+// library's flat JSON
+fn write_json<W: Write>(args: &Args, reports: Vec<Value>, stdout: &mut W) -> Result<(), CliError> {
+    if args.output_dir.is_none() {
+        return writeln!(stdout, "{}", Value::Array(reports))
+            .map_err(|err| CliError::Io("write to stdout".to_string(), err));
+    }
+
+    for (report_index, report) in reports.iter().enumerate() {
+        let write_report = || -> io::Result<()> {
+            let mut file = io::BufWriter::new(create_output(args, report_index)?);
+            match args.format {
+                Format::JsonLines => write_rows(&mut file, report)?,
+                _ => writeln!(file, "{}", report)?,
+            }
+            file.flush()
+        };
+        write_report()
+            .map_err(|err| CliError::Io(format!("write report {}", report_index), err))?;
+    }
+
+    Ok(())
+}
+
+fn run_user_activity<W: Write>(
+    args: &Args,
+    raw_response: &str,
+    stdout: &mut W,
+) -> Result<(), CliError> {
+    let response: UserActivityResponse =
+        serde_json::from_str(raw_response).map_err(FlattenError::from)?;
+
+    match (args.format, &args.output_dir) {
+        (Format::JsonLines, None) => write_rows(stdout, &user_activity_to_row_array(&response)?)
+            .map_err(|err| CliError::Io("write to stdout".to_string(), err)),
+        (Format::Json, _) | (Format::JsonLines, Some(_)) => {
+            write_json(args, vec![user_activity_to_row_array(&response)?], stdout)
+        }
+        (_, Some(_)) => {
+            let mut file = create_output(args, 0)
+                .map(io::BufWriter::new)
+                .map_err(|err| CliError::Io("write report 0".to_string(), err))?;
+            write_user_activity(&response, &mut file, &args.delimited_options)?;
+            file.flush()
+                .map_err(|err| CliError::Io("write report 0".to_string(), err))
+        }
+        (_, None) => Ok(write_user_activity(
+            &response,
+            stdout,
+            &args.delimited_options,
+        )?),
+    }
+}
+
+fn run(args: &Args) -> Result<(), CliError> {
+    if let Some(output_dir) = &args.output_dir {
+        fs::create_dir_all(output_dir)
+            .map_err(|err| CliError::Io(format!("create {}", output_dir.display()), err))?;
+    }
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let json_lines_options = FlatJsonOptions {
+        include_report_index: true,
+        ..args.flat_json_options.clone()
+    };
+
+    // v4 reports are streamed a row at a time when they all go to stdout
+    if args.source == Source::V4 && args.output_dir.is_none() {
+        match args.format {
+            Format::Csv | Format::Tsv => {
+                write_delimited_from_reader(
+                    open_input(args)?,
+                    &mut stdout,
+                    &args.delimited_options,
+                )?;
+                return Ok(());
+            }
+            Format::JsonLines => {
+                write_json_lines_from_reader(open_input(args)?, &mut stdout, &json_lines_options)?;
+                return Ok(());
+            }
+            Format::Json => (),
+        }
+    }
+
+    let raw_response = read_input(args)?;
+    let response: ReportResponse = match args.source {
+        Source::V4 => serde_json::from_str(&raw_response).map_err(FlattenError::from)?,
+        Source::Ga4 => parse_ga4_response(&raw_response)?,
+        Source::Ga4Pivot => parse_ga4_pivot_response(&raw_response, args.pivot_layout)?,
+        Source::V3 => parse_v3_response(&raw_response)?,
+        Source::Mcf => parse_mcf_response(&raw_response, args.path_format)?,
+        Source::UserActivity => return run_user_activity(args, &raw_response, &mut stdout),
+    };
+
+    match (args.format, &args.output_dir) {
+        (Format::Json, _) => {
+            let reports = response_to_row_array(&response, &args.flat_json_options)?;
+            write_json(
+                args,
+                reports.as_array().cloned().unwrap_or_default(),
+                &mut stdout,
+            )?
+        }
+        (Format::JsonLines, None) => {
+            write_json_lines_reports(&response, &mut stdout, &json_lines_options)?
+        }
+        (Format::JsonLines, Some(_)) => {
+            // parse_args turned down summaries and metadata, so every report is an array
+            let reports = response_to_row_array(&response, &args.flat_json_options)?;
+            write_json(
+                args,
+                reports.as_array().cloned().unwrap_or_default(),
+                &mut stdout,
+            )?
+        }
+        (_, Some(_)) => {
+            write_delimited_reports_with(&response, &args.delimited_options, |report_index| {
+                create_output(args, report_index)
+            })?
+        }
+        (_, None) => write_delimited_reports(&response, &mut stdout, &args.delimited_options)?,
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let result = parse_args(env::args().skip(1)).and_then(|args| run(&args));
+
+    match result {
+        Ok(()) => (),
+        Err(CliError::Help) => print!("{}", USAGE),
+        Err(err @ CliError::Usage(_)) => {
+            eprintln!("error: {}", err);
+            process::exit(2);
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, CliError> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn defaults_to_csv_on_stdin() {
+        let args = parse(&[]).unwrap();
+
+        assert_eq!(args.format, Format::Csv);
+        assert_eq!(args.input, None);
+        assert_eq!(args.delimited_options, DelimitedOptions::default());
+    }
+
+    #[test]
+    fn tsv_uses_tabs_unless_a_delimiter_is_given() {
+        assert_eq!(
+            parse(&["--format", "tsv", "report.json"])
+                .unwrap()
+                .delimited_options
+                .delimiter,
+            "\t"
+        );
+        assert_eq!(
+            parse(&["-f", "tsv", "-d", "|"])
+                .unwrap()
+                .delimited_options
+                .delimiter,
+            "|"
+        );
+    }
+
+    #[test]
+    fn mirrors_library_options() {
+        let args = parse(&[
+            "--totals",
+            "--metadata",
+            "--layout",
+            "long",
+            "--quote-style",
+            "necessary",
+            "--no-header",
+            "report.json",
+        ])
+        .unwrap();
+
+        assert_eq!(args.input, Some(PathBuf::from("report.json")));
+        assert!(args.delimited_options.include_totals);
+        assert!(args.delimited_options.include_metadata_comments);
+        assert!(!args.delimited_options.include_header);
+        assert_eq!(args.delimited_options.quote_style, QuoteStyle::Necessary);
+        assert_eq!(args.delimited_options.layout, Layout::Long);
+        assert!(args.flat_json_options.include_totals);
+        assert!(args.flat_json_options.include_metadata);
+        assert_eq!(args.flat_json_options.layout, Layout::Long);
+    }
+
+    #[test]
+    fn fills_in_filename_template() {
+        let args = parse(&["-f", "json", "-o", "out", "-t", "ga_{index}.{ext}"]).unwrap();
+
+        assert_eq!(args.filename(2), "ga_2.json");
+    }
+
+    #[test]
+    fn json_lines_use_the_jsonl_extension() {
+        let args = parse(&["--format", "jsonl", "-o", "out"]).unwrap();
+
+        assert_eq!(args.format, Format::JsonLines);
+        assert_eq!(args.filename(0), "report_0.jsonl");
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        match parse(&["--format", "xml"]) {
+            Err(CliError::Usage(message)) => assert_eq!(message, "unknown format \"xml\""),
+            other => panic!("expected a usage error, got {:?}", other),
+        }
+        match parse(&["--output-dir"]) {
+            Err(CliError::Usage(message)) => assert_eq!(message, "--output-dir needs a value"),
+            other => panic!("expected a usage error, got {:?}", other),
+        }
+        match parse(&["--compare", "first"]) {
+            Err(CliError::Usage(message)) => {
+                assert_eq!(message, "--compare needs a date range index, got \"first\"")
+            }
+            other => panic!("expected a usage error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_more_than_one_input() {
+        match parse(&["first.json", "second.json"]) {
+            Err(CliError::Usage(message)) => assert_eq!(
+                message,
+                "unexpected argument second.json, only one INPUT can be given"
+            ),
+            other => panic!("expected a usage error, got {:?}", other),
+        }
+        assert!(parse(&["-", "report.json"]).is_err());
+        assert_eq!(parse(&["-"]).unwrap().input, None);
+    }
+
+    #[test]
+    fn reads_source_options() {
+        let args = parse(&[
+            "--source",
+            "ga4-pivot",
+            "--pivot-layout",
+            "long",
+            "--paths",
+            "exploded",
+            "--time-format",
+            "iso8601",
+            "--compare",
+            "1",
+        ])
+        .unwrap();
+
+        assert_eq!(args.source, Source::Ga4Pivot);
+        assert_eq!(args.pivot_layout, PivotLayout::Long);
+        assert_eq!(args.path_format, PathFormat::Exploded);
+        assert_eq!(
+            args.flat_json_options.time_format,
+            TimeFormat::Iso8601Duration
+        );
+        assert_eq!(
+            args.delimited_options.comparison,
+            Some(Comparison { base_date_range: 1 })
+        );
+        assert_eq!(parse(&[]).unwrap().source, Source::V4);
+    }
+}
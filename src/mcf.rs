@@ -0,0 +1,323 @@
+use crate::error::{FlattenError, Location};
+use crate::types::*;
+use crate::v3::{V3ColumnHeader, V3ColumnType, V3DataType, V3Response};
+use itertools::Itertools;
+use std::collections::HashMap;
+
+/// How conversion path columns, such as `mcf:basicChannelGroupingPath`, are flattened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PathFormat {
+    /// The steps of the path joined with `PATH_SEPARATOR`, e.g. `Direct > Organic Search > Email`
+    #[default]
+    Joined,
+    /// A row per step of the path, numbered from 1 in a `STEP_COLUMN` dimension. The other
+    /// columns are repeated on every step, so metrics shouldn't be summed across steps, but
+    /// the rows of step 1 add up to the totals: every conversion has one, and one with an
+    /// empty path has just that row, with an empty path.
+    Exploded,
+}
+
+pub const PATH_SEPARATOR: &str = " > ";
+
+/// The name of the dimension holding the step number when paths are exploded. It comes after
+/// the other dimensions and flattens to a `ColumnKind::Step` column, so the number is typed as
+/// an integer in every output.
+pub const STEP_COLUMN: &str = "step";
+
+/// The response of the Multi-Channel Funnels Reporting API `data.mcf.get`.
+///
+/// It is laid out like a v3 response, but each cell is an object holding either a
+/// primitive value or a conversion path.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct McfResponse {
+    pub column_headers: Vec<V3ColumnHeader>,
+    #[serde(default)]
+    pub rows: Vec<Vec<McfCell>>,
+    #[serde(default)]
+    pub totals_for_all_results: HashMap<String, String>,
+    pub total_results: Option<u32>,
+    pub contains_sampled_data: Option<bool>,
+    pub sample_size: Option<String>,
+    pub sample_space: Option<String>,
+    pub next_link: Option<String>,
+    pub kind: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct McfCell {
+    pub primitive_value: Option<String>,
+    pub conversion_path_value: Option<Vec<McfPathNode>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct McfPathNode {
+    pub interaction_type: Option<String>,
+    #[serde(default)]
+    pub node_value: String,
+}
+
+impl McfCell {
+    fn joined_value(&self) -> String {
+        match &self.conversion_path_value {
+            Some(path) => path
+                .iter()
+                .map(|node| &node.node_value)
+                .join(PATH_SEPARATOR),
+            None => self.primitive_value.clone().unwrap_or_default(),
+        }
+    }
+
+    // the value of the cell at a step of the row's paths, empty for a path that
+    // has fewer steps than the row's longest path
+    fn step_value(&self, step: usize) -> String {
+        match &self.conversion_path_value {
+            Some(path) => path
+                .get(step)
+                .map(|node| node.node_value.clone())
+                .unwrap_or_default(),
+            None => self.primitive_value.clone().unwrap_or_default(),
+        }
+    }
+
+    fn number_of_steps(&self) -> Option<usize> {
+        self.conversion_path_value.as_ref().map(Vec::len)
+    }
+}
+
+impl McfResponse {
+    fn check_shape(&self, row_index: usize, row: &[McfCell]) -> Result<(), FlattenError> {
+        if row.len() == self.column_headers.len() {
+            return Ok(());
+        }
+
+        Err(FlattenError::ShapeMismatch {
+            location: Location {
+                report: 0,
+                row: row_index,
+                column: self
+                    .column_headers
+                    .get(row.len())
+                    .map(|header| header.name.clone()),
+            },
+            expected: self.column_headers.len(),
+            found: row.len(),
+        })
+    }
+
+    fn string_rows(&self, path_format: PathFormat) -> Result<Vec<Vec<String>>, FlattenError> {
+        let mut rows = Vec::with_capacity(self.rows.len());
+
+        for (row_index, row) in self.rows.iter().enumerate() {
+            self.check_shape(row_index, row)?;
+
+            match path_format {
+                PathFormat::Joined => rows.push(row.iter().map(McfCell::joined_value).collect()),
+                PathFormat::Exploded => {
+                    // every conversion has a first step, even one with an empty path or
+                    // a row without paths, so none of them go missing
+                    let number_of_steps = row
+                        .iter()
+                        .filter_map(McfCell::number_of_steps)
+                        .max()
+                        .unwrap_or(0)
+                        .max(1);
+                    for step in 0..number_of_steps {
+                        let mut values: Vec<String> =
+                            row.iter().map(|cell| cell.step_value(step)).collect();
+                        values.push((step + 1).to_string());
+                        rows.push(values);
+                    }
+                }
+            }
+        }
+
+        Ok(rows)
+    }
+
+    /// The response as a v4 report, by way of the v3 response it resembles.
+    pub fn to_report(&self, path_format: PathFormat) -> Result<Report, FlattenError> {
+        let mut column_headers = self.column_headers.clone();
+        if path_format == PathFormat::Exploded {
+            // the last column, and so the last of the v3 dimensions
+            column_headers.push(V3ColumnHeader {
+                name: STEP_COLUMN.to_string(),
+                column_type: V3ColumnType::Dimension,
+                data_type: V3DataType::Integer,
+            });
+        }
+
+        let mut report = V3Response {
+            column_headers,
+            rows: self.string_rows(path_format)?,
+            totals_for_all_results: self.totals_for_all_results.clone(),
+            total_results: self.total_results,
+            contains_sampled_data: self.contains_sampled_data,
+            sample_size: self.sample_size.clone(),
+            sample_space: self.sample_space.clone(),
+            next_link: self.next_link.clone(),
+            kind: self.kind.clone(),
+        }
+        .to_report()?;
+        if path_format == PathFormat::Exploded {
+            report.source.step_dimension = Some(report.column_header.dimensions.len() - 1);
+        }
+
+        Ok(report)
+    }
+}
+
+/// Reads a `data.mcf.get` response as a `ReportResponse` with a single report.
+pub fn parse_mcf_response(
+    raw_response: &str,
+    path_format: PathFormat,
+) -> Result<ReportResponse, FlattenError> {
+    let response: McfResponse = serde_json::from_str(raw_response)?;
+
+    Ok(ReportResponse {
+        reports: vec![response.to_report(path_format)?],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flat_table::{Cell, ColumnKind, FlatTable};
+    use serde_json::Number;
+    use std::fs;
+    use std::path::Path;
+
+    fn read_response() -> String {
+        fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/mcf_data.json"))
+            .unwrap()
+    }
+
+    #[test]
+    fn joins_paths() {
+        let response = parse_mcf_response(&read_response(), PathFormat::Joined).unwrap();
+
+        let report = &response.reports[0];
+        assert_eq!(
+            report.column_header.dimensions,
+            vec!["mcf:basicChannelGroupingPath"]
+        );
+        assert_eq!(
+            report.data.rows[0].dimensions,
+            vec!["Direct > Organic Search > Email"]
+        );
+        assert_eq!(report.data.rows[0].metrics[0].values, vec!["12", "840.5"]);
+        assert_eq!(report.data.rows[1].dimensions, vec!["Referral"]);
+    }
+
+    #[test]
+    fn explodes_paths() {
+        let response = parse_mcf_response(&read_response(), PathFormat::Exploded).unwrap();
+
+        let report = &response.reports[0];
+        assert_eq!(
+            report.column_header.dimensions,
+            vec!["mcf:basicChannelGroupingPath", "step"]
+        );
+        assert_eq!(report.source.step_dimension, Some(1));
+        assert_eq!(
+            report.column_header.metric_header.metric_header_entries[0].name,
+            "mcf:totalConversions"
+        );
+        let rows: Vec<(Vec<String>, Vec<String>)> = report
+            .data
+            .rows
+            .iter()
+            .map(|row| (row.dimensions.clone(), row.metrics[0].values.clone()))
+            .collect();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[1].0, vec!["Organic Search", "2"]);
+        assert_eq!(rows[2].1, vec!["12", "840.5"]);
+        assert_eq!(
+            rows[3],
+            (
+                vec!["Referral".to_string(), "1".to_string()],
+                vec!["3".to_string(), "180.0".to_string()]
+            )
+        );
+        assert_eq!(report.data.totals[0].values, vec!["15", "1020.5"]);
+    }
+
+    #[test]
+    fn steps_are_typed_dimensions() {
+        let response = parse_mcf_response(&read_response(), PathFormat::Exploded).unwrap();
+
+        let table = FlatTable::from_report(&response.reports[0], 0, &Default::default()).unwrap();
+
+        assert_eq!(table.columns[1].name, STEP_COLUMN);
+        assert_eq!(table.columns[1].kind, ColumnKind::Step);
+        assert_eq!(table.columns[1].metric_type, None);
+        assert_eq!(table.rows[2][1], Cell::Number(Number::from(3)));
+        assert_eq!(table.totals.unwrap()[0][1], Cell::Empty);
+    }
+
+    #[test]
+    fn exploded_first_steps_add_up_to_the_joined_totals() {
+        let raw_response = r#"{
+            "columnHeaders": [
+                {"name": "mcf:sourcePath", "columnType": "DIMENSION", "dataType": "MCF_SEQUENCE"},
+                {"name": "mcf:totalConversions", "columnType": "METRIC", "dataType": "INTEGER"}
+            ],
+            "rows": [
+                [{"conversionPathValue": []}, {"primitiveValue": "2"}],
+                [{"conversionPathValue": [{"nodeValue": "google"}, {"nodeValue": "bing"}]}, {"primitiveValue": "5"}],
+                [{"conversionPathValue": [{"nodeValue": "google"}]}, {"primitiveValue": "4"}]
+            ],
+            "totalsForAllResults": {"mcf:totalConversions": "11"}
+        }"#;
+        let conversions = |path_format: PathFormat| {
+            let response = parse_mcf_response(raw_response, path_format).unwrap();
+            let table =
+                FlatTable::from_report(&response.reports[0], 0, &Default::default()).unwrap();
+            let first_steps: i64 = table
+                .rows
+                .iter()
+                .filter(|cells| {
+                    path_format == PathFormat::Joined || cells[1] == Cell::Number(Number::from(1))
+                })
+                .map(|cells| match cells.last() {
+                    Some(Cell::Metric { number, .. }) => number.as_i64().unwrap(),
+                    other => panic!("expected a metric, got {:?}", other),
+                })
+                .sum();
+
+            (first_steps, table.totals)
+        };
+
+        let (joined, joined_totals) = conversions(PathFormat::Joined);
+        let (exploded, exploded_totals) = conversions(PathFormat::Exploded);
+        assert_eq!(joined, 11);
+        assert_eq!(exploded, joined);
+        assert_eq!(exploded_totals.unwrap()[0][2], joined_totals.unwrap()[0][1]);
+
+        let report = &parse_mcf_response(raw_response, PathFormat::Exploded)
+            .unwrap()
+            .reports[0];
+        assert_eq!(report.data.rows.len(), 4);
+        assert_eq!(report.data.rows[0].dimensions, vec!["", "1"]);
+        assert_eq!(report.data.rows[0].metrics[0].values, vec!["2"]);
+        assert_eq!(report.data.totals[0].values, vec!["11"]);
+    }
+
+    #[test]
+    fn rejects_short_rows() {
+        let raw_response = r#"{
+            "columnHeaders": [
+                {"name": "mcf:sourcePath", "columnType": "DIMENSION", "dataType": "MCF_SEQUENCE"},
+                {"name": "mcf:totalConversions", "columnType": "METRIC", "dataType": "INTEGER"}
+            ],
+            "rows": [[{"conversionPathValue": [{"nodeValue": "google"}]}]]
+        }"#;
+
+        match parse_mcf_response(raw_response, PathFormat::Exploded) {
+            Err(FlattenError::ShapeMismatch { location, .. }) => assert_eq!(location.row, 0),
+            other => panic!("expected a shape mismatch, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use crate::error::FlattenError;
+use crate::types::*;
+
+/// Combines the pages of a paginated `batchGet` into a single response.
+///
+/// Every page must have the same reports, in the same order, with the same column headers.
+/// Each merged report has the rows of all its pages, in page order, along with the totals and
+/// other data of the first page (they describe the whole report, so they are the same on every
+/// page) and the `next_page_token` of the last page.
+pub fn merge_pages(pages: Vec<ReportResponse>) -> Result<ReportResponse, FlattenError> {
+    let mut page_iter = pages.into_iter();
+
+    let mut merged = match page_iter.next() {
+        Some(first_page) => first_page,
+        None => return Ok(ReportResponse { reports: vec![] }),
+    };
+
+    for (page_index, page) in page_iter.enumerate().map(|(i, page)| (i + 1, page)) {
+        if page.reports.len() != merged.reports.len() {
+            return Err(FlattenError::PageMismatch {
+                page: page_index,
+                report: None,
+            });
+        }
+
+        for (report_index, (merged_report, report)) in
+            merged.reports.iter_mut().zip(page.reports).enumerate()
+        {
+            if report.column_header != merged_report.column_header {
+                return Err(FlattenError::PageMismatch {
+                    page: page_index,
+                    report: Some(report_index),
+                });
+            }
+
+            merged_report.data.rows.extend(report.data.rows);
+            merged_report.next_page_token = report.next_page_token;
+        }
+    }
+
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+    use std::fs;
+    use std::path::Path;
+
+    fn read_page(name: &str) -> ReportResponse {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("test_reports")
+                .join(name),
+        )
+        .unwrap();
+
+        serde_json::from_str(data.as_str()).unwrap()
+    }
+
+    #[test]
+    fn concatenates_rows() {
+        let merged = merge_pages(vec![
+            read_page("paginated_page_1.json"),
+            read_page("paginated_page_2.json"),
+        ])
+        .unwrap();
+
+        let report = &merged.reports[0];
+        assert_eq!(
+            report
+                .data
+                .rows
+                .iter()
+                .map(|row| row.dimensions[0].as_str())
+                .collect::<Vec<&str>>(),
+            vec!["desktop", "mobile", "tablet"]
+        );
+        assert_eq!(report.data.totals[0].values, vec!["50"]);
+        assert_eq!(report.next_page_token, None);
+    }
+
+    #[test]
+    fn no_pages() {
+        assert!(merge_pages(vec![]).unwrap().reports.is_empty())
+    }
+
+    #[test]
+    fn rejects_mismatched_column_headers() {
+        match merge_pages(vec![
+            read_page("paginated_page_1.json"),
+            read_page("multiple_dimensions_and_metrics.json"),
+        ]) {
+            Err(FlattenError::PageMismatch {
+                page: 1,
+                report: Some(0),
+            }) => (),
+            other => panic!("expected a page mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_different_numbers_of_reports() {
+        match merge_pages(vec![
+            read_page("paginated_page_1.json"),
+            read_page("multiple_reports.json"),
+        ]) {
+            Err(FlattenError::PageMismatch {
+                page: 1,
+                report: None,
+            }) => (),
+            other => panic!("expected a page mismatch, got {:?}", other),
+        }
+    }
+}
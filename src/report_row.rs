@@ -0,0 +1,249 @@
+use crate::error::{FlattenError, HeaderKind, HeaderMismatch};
+use crate::from_report::deserialize_rows;
+use crate::types::{ColumnHeader, Report};
+use itertools::EitherOrBoth::{Both, Left, Right};
+use itertools::Itertools;
+use serde::de::DeserializeOwned;
+use serde_json::value::Value;
+
+/// A type each row of a report deserializes into, declaring the dimensions and metrics the
+/// report is requested with. Usually derived with `#[derive(GaReportRow)]` from the `derive`
+/// feature, which takes them from `#[ga(dimension = "...")]` and `#[ga(metric = "...")]`
+/// field attributes.
+pub trait GaReportRow: DeserializeOwned {
+    /// The dimensions in request order
+    const DIMENSIONS: &'static [&'static str];
+    /// The metrics in request order
+    const METRICS: &'static [&'static str];
+    /// The field each of `DIMENSIONS` and then each of `METRICS` fills
+    const FIELDS: &'static [&'static str];
+
+    /// The `dimensions` and `metrics` of a `ReportRequest` asking for the declared columns,
+    /// to merge into the rest of the request body.
+    fn request_columns() -> Value {
+        json!({
+            "dimensions": Self::DIMENSIONS
+                .iter()
+                .map(|name| json!({ "name": name }))
+                .collect::<Vec<_>>(),
+            "metrics": Self::METRICS
+                .iter()
+                .map(|expression| json!({ "expression": expression }))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    /// Checks that the report has exactly the declared dimensions and metrics, in order,
+    /// failing on the first one that differs.
+    fn check_column_header(
+        column_header: &ColumnHeader,
+        report_index: usize,
+    ) -> Result<(), FlattenError> {
+        let metrics: Vec<&str> = column_header
+            .metric_header
+            .metric_header_entries
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+
+        check_names(
+            report_index,
+            HeaderKind::Dimension,
+            Self::DIMENSIONS,
+            column_header.dimensions.iter().map(String::as_str),
+        )?;
+        check_names(
+            report_index,
+            HeaderKind::Metric,
+            Self::METRICS,
+            metrics.into_iter(),
+        )
+    }
+
+    /// Checks the report's column header, then deserializes each row into a `Self` like
+    /// `from_report` does, with each declared column filling its field. Metrics of later
+    /// date ranges keep their suffixed names, e.g. `ga:sessions_2`.
+    fn from_report(report: &Report, report_index: usize) -> Result<Vec<Self>, FlattenError> {
+        Self::check_column_header(&report.column_header, report_index)?;
+
+        deserialize_rows(report, report_index, |name| {
+            Self::DIMENSIONS
+                .iter()
+                .chain(Self::METRICS.iter())
+                .position(|declared| *declared == name)
+                .and_then(|index| Self::FIELDS.get(index))
+                .unwrap_or(&name)
+                .to_string()
+        })
+    }
+}
+
+fn check_names<'h>(
+    report_index: usize,
+    kind: HeaderKind,
+    declared: &[&str],
+    found: impl Iterator<Item = &'h str>,
+) -> Result<(), FlattenError> {
+    for (position, names) in declared.iter().zip_longest(found).enumerate() {
+        let mismatch = match names {
+            Both(expected, found) if *expected == found => continue,
+            Both(expected, found) => HeaderMismatch::Different {
+                expected: expected.to_string(),
+                found: found.to_string(),
+            },
+            Left(expected) => HeaderMismatch::Missing {
+                expected: expected.to_string(),
+            },
+            Right(found) => HeaderMismatch::Undeclared {
+                found: found.to_string(),
+            },
+        };
+
+        return Err(FlattenError::ColumnHeaderMismatch {
+            report: report_index,
+            kind,
+            position,
+            mismatch,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ReportResponse;
+    use std::fs;
+    use std::path::Path;
+
+    fn read_response(name: &str) -> ReportResponse {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("test_reports")
+                .join(name),
+        )
+        .unwrap();
+
+        serde_json::from_str(data.as_str()).unwrap()
+    }
+
+    // what `#[derive(GaReportRow)]` generates for
+    //     struct DeviceRow {
+    //         #[ga(dimension = "ga:deviceCategory")]
+    //         device: String,
+    //         #[ga(metric = "ga:sessions")]
+    //         sessions: u32,
+    //     }
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct DeviceRow {
+        device: String,
+        sessions: u32,
+    }
+
+    impl GaReportRow for DeviceRow {
+        const DIMENSIONS: &'static [&'static str] = &["ga:deviceCategory"];
+        const METRICS: &'static [&'static str] = &["ga:sessions"];
+        const FIELDS: &'static [&'static str] = &["device", "sessions"];
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct UsersRow {
+        device: String,
+        sessions: u32,
+        users: u32,
+    }
+
+    impl GaReportRow for UsersRow {
+        const DIMENSIONS: &'static [&'static str] = &["ga:deviceCategory"];
+        const METRICS: &'static [&'static str] = &["ga:sessions", "ga:users"];
+        const FIELDS: &'static [&'static str] = &["device", "sessions", "users"];
+    }
+
+    #[test]
+    fn fills_declared_fields() {
+        let response = read_response("single_dimension_and_metric.json");
+
+        let rows = DeviceRow::from_report(&response.reports[0], 0).unwrap();
+
+        assert_eq!(
+            rows,
+            vec![
+                DeviceRow {
+                    device: "desktop".to_string(),
+                    sessions: 43,
+                },
+                DeviceRow {
+                    device: "mobile".to_string(),
+                    sessions: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn request_columns() {
+        assert_eq!(
+            UsersRow::request_columns(),
+            json!({
+                "dimensions": [{ "name": "ga:deviceCategory" }],
+                "metrics": [
+                    { "expression": "ga:sessions" },
+                    { "expression": "ga:users" },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn reports_missing_columns() {
+        let response = read_response("single_dimension_and_metric.json");
+
+        match UsersRow::from_report(&response.reports[0], 2) {
+            Err(err @ FlattenError::ColumnHeaderMismatch { .. }) => assert_eq!(
+                err.to_string(),
+                "report 2 is missing declared metric \"ga:users\" at position 1"
+            ),
+            other => panic!("expected a column header mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reports_different_and_undeclared_columns() {
+        let mut response = read_response("single_dimension_and_metric.json");
+        let column_header = &mut response.reports[0].column_header;
+        column_header.dimensions[0] = "ga:browser".to_string();
+
+        match DeviceRow::check_column_header(column_header, 0) {
+            Err(err @ FlattenError::ColumnHeaderMismatch { .. }) => assert_eq!(
+                err.to_string(),
+                "report 0 has dimension \"ga:browser\" at position 0 where \
+                 \"ga:deviceCategory\" was declared"
+            ),
+            other => panic!("expected a column header mismatch, got {:?}", other),
+        }
+
+        column_header.dimensions[0] = "ga:deviceCategory".to_string();
+        column_header.dimensions.push("ga:country".to_string());
+
+        match DeviceRow::check_column_header(column_header, 0) {
+            Err(FlattenError::ColumnHeaderMismatch {
+                kind,
+                position,
+                mismatch,
+                ..
+            }) => {
+                assert_eq!(kind, HeaderKind::Dimension);
+                assert_eq!(position, 1);
+                assert_eq!(
+                    mismatch,
+                    HeaderMismatch::Undeclared {
+                        found: "ga:country".to_string()
+                    }
+                );
+            }
+            other => panic!("expected a column header mismatch, got {:?}", other),
+        }
+    }
+}
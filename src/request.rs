@@ -0,0 +1,148 @@
+use crate::error::FlattenError;
+
+/// The body of a `reports:batchGet` call. Only the parts needed to describe the
+/// response are read, everything else in the request is ignored.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchGetRequest {
+    pub report_requests: Vec<ReportRequest>,
+}
+
+impl BatchGetRequest {
+    pub fn date_range_labels(&self) -> Vec<Vec<String>> {
+        self.report_requests
+            .iter()
+            .map(ReportRequest::date_range_labels)
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportRequest {
+    // required, even though the API defaults it, so that an object that isn't a
+    // report request at all isn't read as one without date ranges
+    pub date_ranges: Vec<DateRange>,
+}
+
+impl ReportRequest {
+    pub fn date_range_labels(&self) -> Vec<String> {
+        self.date_ranges.iter().map(DateRange::label).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DateRange {
+    pub start_date: String,
+    pub end_date: String,
+}
+
+impl DateRange {
+    // 2024-01-01..2024-01-31
+    pub fn label(&self) -> String {
+        format!("{}..{}", self.start_date, self.end_date)
+    }
+}
+
+/// Reads the date range labels for each report from a `batchGet` request body, a single
+/// `ReportRequest`, or a list of `{"startDate": ..., "endDate": ...}` pairs for one report.
+/// Every report request needs its `dateRanges`.
+pub fn date_range_labels_from_request(raw_request: &str) -> Result<Vec<Vec<String>>, FlattenError> {
+    // the kind of request is picked by its shape, so parsing it fails with what's
+    // actually wrong rather than with it not looking like any of them
+    let raw_request: serde_json::Value = serde_json::from_str(raw_request)?;
+    let labels = if raw_request.is_array() {
+        let date_ranges: Vec<DateRange> = serde_json::from_value(raw_request)?;
+        vec![date_ranges.iter().map(DateRange::label).collect()]
+    } else if raw_request.get("reportRequests").is_some() {
+        let request: BatchGetRequest = serde_json::from_value(raw_request)?;
+        request.date_range_labels()
+    } else {
+        let request: ReportRequest = serde_json::from_value(raw_request)?;
+        vec![request.date_range_labels()]
+    };
+
+    Ok(labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_from_batch_get_request() {
+        let raw_request = r#"{
+            "reportRequests": [{
+                "viewId": "12345",
+                "dateRanges": [
+                    {"startDate": "2024-02-01", "endDate": "2024-02-29"},
+                    {"startDate": "2024-01-01", "endDate": "2024-01-31"}
+                ],
+                "metrics": [{"expression": "ga:sessions"}]
+            }, {
+                "viewId": "12345",
+                "dateRanges": [{"startDate": "7daysAgo", "endDate": "yesterday"}]
+            }]
+        }"#;
+
+        assert_eq!(
+            date_range_labels_from_request(raw_request).unwrap(),
+            vec![
+                vec!["2024-02-01..2024-02-29", "2024-01-01..2024-01-31"],
+                vec!["7daysAgo..yesterday"],
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_from_report_request() {
+        let raw_request = r#"{
+            "viewId": "12345",
+            "dateRanges": [{"startDate": "2024-01-01", "endDate": "2024-01-31"}]
+        }"#;
+
+        assert_eq!(
+            date_range_labels_from_request(raw_request).unwrap(),
+            vec![vec!["2024-01-01..2024-01-31"]]
+        );
+    }
+
+    #[test]
+    fn labels_from_date_ranges() {
+        let raw_request = r#"[
+            {"startDate": "2024-01-01", "endDate": "2024-01-31"},
+            {"startDate": "2023-01-01", "endDate": "2023-01-31"}
+        ]"#;
+
+        assert_eq!(
+            date_range_labels_from_request(raw_request).unwrap(),
+            vec![vec!["2024-01-01..2024-01-31", "2023-01-01..2023-01-31"]]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_requests() {
+        match date_range_labels_from_request("[{\"startDate\": \"2024-01-01\"}]") {
+            Err(FlattenError::Json(_)) => (),
+            other => panic!("expected a JSON error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_requests_without_date_ranges() {
+        for raw_request in [
+            r#"{"reportRequest": []}"#,
+            r#"{"reportRequests": [{"viewId": "12345"}]}"#,
+        ]
+        .iter()
+        {
+            match date_range_labels_from_request(raw_request) {
+                Err(FlattenError::Json(err)) => {
+                    assert!(err.to_string().contains("missing field `dateRanges`"))
+                }
+                other => panic!("expected a JSON error, got {:?}", other),
+            }
+        }
+    }
+}
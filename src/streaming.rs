@@ -0,0 +1,436 @@
+use crate::error::FlattenError;
+use crate::types::*;
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Deserializer;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+
+/// Receives a report response a piece at a time as it is parsed.
+///
+/// For each report, `start_report` is called once with the column header, then `row` for
+/// each row in order, then `end_report` with the rest of the report (its totals and other
+/// `data` fields, but no rows). Rows are only held in memory while they are handled, unless
+/// a report's `data` comes before its `columnHeader`, in which case that report's rows are
+/// kept until the header has been read.
+pub trait RowHandler {
+    fn start_report(
+        &mut self,
+        report_index: usize,
+        column_header: &ColumnHeader,
+    ) -> Result<(), FlattenError>;
+
+    fn row(&mut self, report_index: usize, row: ReportRow) -> Result<(), FlattenError>;
+
+    fn end_report(&mut self, report_index: usize, report: &Report) -> Result<(), FlattenError>;
+}
+
+/// Parses a report response from `reader`, passing each report and row to `handler` as
+/// soon as it has been read.
+pub fn stream_report_response<R: Read, H: RowHandler>(
+    reader: R,
+    handler: &mut H,
+) -> Result<(), FlattenError> {
+    let mut reader = BufReader::new(reader);
+    if reader.fill_buf()?.is_empty() {
+        return Ok(());
+    }
+
+    let mut state = StreamState {
+        handler,
+        error: None,
+    };
+
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let result = ResponseSeed { state: &mut state }
+        .deserialize(&mut deserializer)
+        .and_then(|_| deserializer.end());
+
+    // errors from the handler are smuggled through serde as a custom error,
+    // so hand back the original instead
+    match (state.error, result) {
+        (Some(err), _) => Err(err),
+        (None, Err(err)) => Err(FlattenError::Json(err)),
+        (None, Ok(())) => Ok(()),
+    }
+}
+
+struct StreamState<'h, H> {
+    handler: &'h mut H,
+    error: Option<FlattenError>,
+}
+
+impl<'h, H: RowHandler> StreamState<'h, H> {
+    fn handle<E: de::Error>(
+        &mut self,
+        call: impl FnOnce(&mut H) -> Result<(), FlattenError>,
+    ) -> Result<(), E> {
+        call(self.handler).map_err(|err| {
+            let message = err.to_string();
+            self.error = Some(err);
+            E::custom(message)
+        })
+    }
+}
+
+struct ResponseSeed<'s, 'h, H> {
+    state: &'s mut StreamState<'h, H>,
+}
+
+impl<'de, 's, 'h, H: RowHandler> DeserializeSeed<'de> for ResponseSeed<'s, 'h, H> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 's, 'h, H: RowHandler> Visitor<'de> for ResponseSeed<'s, 'h, H> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a report response")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let mut has_reports = false;
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "reports" => {
+                    map.next_value_seed(ReportsSeed {
+                        state: &mut *self.state,
+                    })?;
+                    has_reports = true;
+                }
+                other => return Err(de::Error::unknown_field(other, &["reports"])),
+            }
+        }
+
+        if has_reports {
+            Ok(())
+        } else {
+            Err(de::Error::missing_field("reports"))
+        }
+    }
+}
+
+struct ReportsSeed<'s, 'h, H> {
+    state: &'s mut StreamState<'h, H>,
+}
+
+impl<'de, 's, 'h, H: RowHandler> DeserializeSeed<'de> for ReportsSeed<'s, 'h, H> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 's, 'h, H: RowHandler> Visitor<'de> for ReportsSeed<'s, 'h, H> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of reports")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let mut report_index = 0;
+
+        while seq
+            .next_element_seed(ReportSeed {
+                state: &mut *self.state,
+                report_index,
+            })?
+            .is_some()
+        {
+            report_index += 1;
+        }
+
+        Ok(())
+    }
+}
+
+struct ReportSeed<'s, 'h, H> {
+    state: &'s mut StreamState<'h, H>,
+    report_index: usize,
+}
+
+impl<'de, 's, 'h, H: RowHandler> DeserializeSeed<'de> for ReportSeed<'s, 'h, H> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 's, 'h, H: RowHandler> Visitor<'de> for ReportSeed<'s, 'h, H> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a report")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let report_index = self.report_index;
+        let mut report = ReportProgress {
+            column_header: None,
+            pending_rows: Vec::new(),
+        };
+        let mut data: Option<ReportData> = None;
+        let mut next_page_token: Option<String> = None;
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "columnHeader" => {
+                    let column_header: ColumnHeader = map.next_value()?;
+                    self.state.handle(|handler| {
+                        handler.start_report(report_index, &column_header)?;
+                        for row in report.pending_rows.drain(..) {
+                            handler.row(report_index, row)?;
+                        }
+                        Ok(())
+                    })?;
+                    report.column_header = Some(column_header);
+                }
+                "data" => {
+                    data = Some(map.next_value_seed(DataSeed {
+                        state: &mut *self.state,
+                        report: &mut report,
+                        report_index,
+                    })?);
+                }
+                "nextPageToken" => {
+                    next_page_token = map.next_value()?;
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        let report = Report {
+            column_header: report
+                .column_header
+                .ok_or_else(|| de::Error::missing_field("columnHeader"))?,
+            data: data.ok_or_else(|| de::Error::missing_field("data"))?,
+            next_page_token,
+            source: ReportSource::default(),
+        };
+
+        self.state
+            .handle(|handler| handler.end_report(report_index, &report))
+    }
+}
+
+struct ReportProgress {
+    column_header: Option<ColumnHeader>,
+    pending_rows: Vec<ReportRow>,
+}
+
+struct DataSeed<'s, 'h, 'r, H> {
+    state: &'s mut StreamState<'h, H>,
+    report: &'r mut ReportProgress,
+    report_index: usize,
+}
+
+impl<'de, 's, 'h, 'r, H: RowHandler> DeserializeSeed<'de> for DataSeed<'s, 'h, 'r, H> {
+    type Value = ReportData;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<ReportData, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 's, 'h, 'r, H: RowHandler> Visitor<'de> for DataSeed<'s, 'h, 'r, H> {
+    type Value = ReportData;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("report data")
+    }
+
+    // everything but the rows is small, so it is collected and deserialized as usual
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<ReportData, A::Error> {
+        let mut other_fields: Map<String, Value> = Map::new();
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "rows" => map.next_value_seed(RowsSeed {
+                    state: &mut *self.state,
+                    report: &mut *self.report,
+                    report_index: self.report_index,
+                })?,
+                _ => {
+                    other_fields.insert(key, map.next_value()?);
+                }
+            }
+        }
+
+        serde_json::from_value(Value::Object(other_fields)).map_err(de::Error::custom)
+    }
+}
+
+struct RowsSeed<'s, 'h, 'r, H> {
+    state: &'s mut StreamState<'h, H>,
+    report: &'r mut ReportProgress,
+    report_index: usize,
+}
+
+impl<'de, 's, 'h, 'r, H: RowHandler> DeserializeSeed<'de> for RowsSeed<'s, 'h, 'r, H> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 's, 'h, 'r, H: RowHandler> Visitor<'de> for RowsSeed<'s, 'h, 'r, H> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of rows")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let report_index = self.report_index;
+
+        while let Some(row) = seq.next_element::<ReportRow>()? {
+            if self.report.column_header.is_some() {
+                self.state
+                    .handle(|handler| handler.row(report_index, row))?;
+            } else {
+                self.report.pending_rows.push(row);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    #[derive(Default)]
+    struct RecordingHandler {
+        events: Vec<String>,
+    }
+
+    impl RowHandler for RecordingHandler {
+        fn start_report(
+            &mut self,
+            report_index: usize,
+            column_header: &ColumnHeader,
+        ) -> Result<(), FlattenError> {
+            self.events.push(format!(
+                "start {} {}",
+                report_index,
+                column_header.dimensions.join(",")
+            ));
+            Ok(())
+        }
+
+        fn row(&mut self, report_index: usize, row: ReportRow) -> Result<(), FlattenError> {
+            self.events
+                .push(format!("row {} {}", report_index, row.dimensions.join(",")));
+            Ok(())
+        }
+
+        fn end_report(&mut self, report_index: usize, report: &Report) -> Result<(), FlattenError> {
+            self.events.push(format!(
+                "end {} {}",
+                report_index,
+                report.data.totals[0].values.join(",")
+            ));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn multiple_reports() {
+        let data = fs::File::open(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/multiple_reports.json"),
+        )
+        .unwrap();
+
+        let mut handler = RecordingHandler::default();
+        stream_report_response(data, &mut handler).unwrap();
+
+        assert_eq!(
+            handler.events,
+            vec![
+                "start 0 ga:deviceCategory",
+                "row 0 desktop",
+                "row 0 mobile",
+                "end 0 27,19",
+                "start 1 ga:country",
+                "row 1 Azerbaijan",
+                "row 1 France",
+                "row 1 Japan",
+                "row 1 Switzerland",
+                "row 1 United States",
+                "end 1 27,19",
+            ]
+        )
+    }
+
+    #[test]
+    fn data_before_column_header() {
+        let data = r#"{"reports": [{
+            "data": {
+                "rows": [{"dimensions": ["desktop"], "metrics": [{"values": ["1"]}]}],
+                "totals": [{"values": ["1"]}]
+            },
+            "columnHeader": {
+                "dimensions": ["ga:deviceCategory"],
+                "metricHeader": {"metricHeaderEntries": [{"name": "ga:sessions", "type": "INTEGER"}]}
+            }
+        }]}"#;
+
+        let mut handler = RecordingHandler::default();
+        stream_report_response(data.as_bytes(), &mut handler).unwrap();
+
+        assert_eq!(
+            handler.events,
+            vec!["start 0 ga:deviceCategory", "row 0 desktop", "end 0 1"]
+        )
+    }
+
+    #[test]
+    fn empty_input() {
+        let mut handler = RecordingHandler::default();
+        stream_report_response("".as_bytes(), &mut handler).unwrap();
+
+        assert!(handler.events.is_empty())
+    }
+
+    #[test]
+    fn handler_errors_are_returned_as_is() {
+        struct FailingHandler;
+
+        impl RowHandler for FailingHandler {
+            fn start_report(&mut self, _: usize, _: &ColumnHeader) -> Result<(), FlattenError> {
+                Err(FlattenError::UnsupportedFeature("anything".to_string()))
+            }
+
+            fn row(&mut self, _: usize, _: ReportRow) -> Result<(), FlattenError> {
+                Ok(())
+            }
+
+            fn end_report(&mut self, _: usize, _: &Report) -> Result<(), FlattenError> {
+                Ok(())
+            }
+        }
+
+        let data =
+            fs::File::open(Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/no_rows.json"))
+                .unwrap();
+
+        match stream_report_response(data, &mut FailingHandler) {
+            Err(FlattenError::UnsupportedFeature(_)) => (),
+            other => panic!("expected the handler's error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use crate::error::{FlattenError, Location};
+use crate::flat_table::{Cell, Column, ColumnKind, FlatTable};
+use crate::types::{MetricType, Report, ReportResponse};
+use arrow::array::{ArrayRef, Float64Builder, Int64Builder, StringBuilder};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::error::ArrowError;
+use arrow::ipc::writer::{FileWriter, StreamWriter};
+use arrow::record_batch::RecordBatch;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Converts every report in the response to a `RecordBatch`, see `report_to_record_batch`.
+pub fn response_to_record_batches(
+    response: &ReportResponse,
+) -> Result<Vec<RecordBatch>, FlattenError> {
+    response
+        .reports
+        .iter()
+        .enumerate()
+        .map(|(report_index, report)| report_to_record_batch(report, report_index))
+        .collect()
+}
+
+/// Converts a report to a `RecordBatch` with a `Utf8` column per dimension and a column per
+/// metric and date range, named like the delimited output's columns. `INTEGER` metrics are
+/// `Int64` columns, every other metric type is `Float64`. Every column is nullable, and empty
+/// cells are nulls.
+pub fn report_to_record_batch(
+    report: &Report,
+    report_index: usize,
+) -> Result<RecordBatch, FlattenError> {
+    let table = FlatTable::from_report(report, report_index, &Default::default())?;
+    table_to_record_batch(&table, report_index)
+}
+
+/// Converts a flattened report to a `RecordBatch`, with columns typed as in
+/// `report_to_record_batch`. Date range columns are `Utf8` and comparison columns `Float64`.
+pub fn table_to_record_batch(
+    table: &FlatTable,
+    report_index: usize,
+) -> Result<RecordBatch, FlattenError> {
+    let schema = Arc::new(table_schema(&table.columns));
+
+    let mut builders: Vec<ColumnBuilder> = table
+        .columns
+        .iter()
+        .map(|column| ColumnBuilder::new(column, table.rows.len()))
+        .collect();
+
+    for (table_row, cells) in table.rows.iter().enumerate() {
+        for ((builder, column), cell) in builders
+            .iter_mut()
+            .zip(table.columns.iter())
+            .zip(cells.iter())
+        {
+            builder
+                .append(cell)
+                .ok_or_else(|| FlattenError::InvalidMetricValue {
+                    location: Location {
+                        report: report_index,
+                        row: table.report_row_index(table_row),
+                        column: Some(column.name.clone()),
+                    },
+                    value: match cell {
+                        Cell::Metric { text, .. } => text.clone(),
+                        _ => String::new(),
+                    },
+                })?;
+        }
+    }
+
+    let columns: Vec<ArrayRef> = builders.iter_mut().map(ColumnBuilder::finish).collect();
+
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+/// Writes the batch to `writer` in the Arrow IPC file format.
+pub fn write_ipc_file<W: Write>(batch: &RecordBatch, writer: W) -> Result<(), FlattenError> {
+    let mut writer = FileWriter::try_new(writer, &batch.schema())?;
+    writer.write(batch)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// Writes the batch to `writer` in the Arrow IPC streaming format.
+pub fn write_ipc_stream<W: Write>(batch: &RecordBatch, writer: W) -> Result<(), FlattenError> {
+    let mut writer = StreamWriter::try_new(writer, &batch.schema())?;
+    writer.write(batch)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+fn table_schema(columns: &[Column]) -> Schema {
+    let fields: Vec<Field> = columns
+        .iter()
+        .map(|column| {
+            // any cell can be empty, like the metrics GA4 pivots leave out or the
+            // dimensions of summary rows
+            Field::new(column.name.as_str(), column_data_type(column), true)
+        })
+        .collect();
+
+    Schema::new(fields)
+}
+
+fn column_data_type(column: &Column) -> DataType {
+    match (column.kind, &column.metric_type) {
+        (ColumnKind::Dimension, _) | (ColumnKind::DateRange, _) => DataType::Utf8,
+        (ColumnKind::Metric, Some(MetricType::Integer)) | (ColumnKind::Step, _) => DataType::Int64,
+        _ => DataType::Float64,
+    }
+}
+
+enum ColumnBuilder {
+    Text(StringBuilder),
+    Integer(Int64Builder),
+    Float(Float64Builder),
+}
+
+impl ColumnBuilder {
+    fn new(column: &Column, capacity: usize) -> Self {
+        match column_data_type(column) {
+            DataType::Utf8 => ColumnBuilder::Text(StringBuilder::with_capacity(capacity, 0)),
+            DataType::Int64 => ColumnBuilder::Integer(Int64Builder::with_capacity(capacity)),
+            _ => ColumnBuilder::Float(Float64Builder::with_capacity(capacity)),
+        }
+    }
+
+    // None when a metric isn't a number of the column's type, like an INTEGER
+    // metric with a fractional value
+    fn append(&mut self, cell: &Cell) -> Option<()> {
+        match (self, cell) {
+            (ColumnBuilder::Text(builder), Cell::Text(text)) => builder.append_value(text),
+            (ColumnBuilder::Text(builder), Cell::Number(number)) => {
+                builder.append_value(number.to_string())
+            }
+            (ColumnBuilder::Integer(builder), Cell::Metric { number, .. })
+            | (ColumnBuilder::Integer(builder), Cell::Number(number)) => {
+                builder.append_value(number.as_i64()?)
+            }
+            (ColumnBuilder::Float(builder), Cell::Metric { number, .. })
+            | (ColumnBuilder::Float(builder), Cell::Number(number)) => {
+                builder.append_value(number.as_f64()?)
+            }
+            (ColumnBuilder::Text(builder), _) => builder.append_null(),
+            (ColumnBuilder::Integer(builder), _) => builder.append_null(),
+            (ColumnBuilder::Float(builder), _) => builder.append_null(),
+        }
+
+        Some(())
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            ColumnBuilder::Text(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Integer(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Float(builder) => Arc::new(builder.finish()),
+        }
+    }
+}
+
+impl From<ArrowError> for FlattenError {
+    fn from(err: ArrowError) -> Self {
+        FlattenError::Arrow(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{Float64Array, Int64Array, StringArray};
+    use arrow::ipc::reader::{FileReader, StreamReader};
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::Path;
+
+    fn read_response(name: &str) -> ReportResponse {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("test_reports")
+                .join(name),
+        )
+        .unwrap();
+
+        serde_json::from_str(data.as_str()).unwrap()
+    }
+
+    #[test]
+    fn typed_columns() {
+        let response = read_response("metric_types.json");
+
+        let batch = report_to_record_batch(&response.reports[0], 0).unwrap();
+
+        let schema = batch.schema();
+        let fields: Vec<(&str, &DataType)> = schema
+            .fields()
+            .iter()
+            .map(|field| (field.name().as_str(), field.data_type()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("ga:country", &DataType::Utf8),
+                ("ga:sessions", &DataType::Int64),
+                ("ga:bounceRate", &DataType::Float64),
+                ("ga:transactionRevenue", &DataType::Float64),
+                ("ga:pageviewsPerSession", &DataType::Float64),
+                ("ga:avgSessionDuration", &DataType::Float64),
+            ]
+        );
+        assert_eq!(batch.num_rows(), 1);
+
+        let countries = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(countries.value(0), "Canada");
+        let sessions = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(sessions.value(0), 12);
+        let durations = batch
+            .column(5)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(durations.value(0), 3725.5);
+    }
+
+    #[test]
+    fn multiple_date_ranges() {
+        let response = read_response("multiple_date_ranges.json");
+
+        let batch = report_to_record_batch(&response.reports[0], 0).unwrap();
+
+        let metric_names: Vec<String> = response.reports[0]
+            .get_metric_headers()
+            .into_iter()
+            .map(|header| header.name)
+            .collect();
+        let schema = batch.schema();
+        let column_names: Vec<&String> = schema
+            .fields()
+            .iter()
+            .skip(response.reports[0].column_header.dimensions.len())
+            .map(|field| field.name())
+            .collect();
+        assert_eq!(column_names, metric_names.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn empty_report() {
+        let response = read_response("no_rows.json");
+
+        let batch = report_to_record_batch(&response.reports[0], 0).unwrap();
+
+        assert_eq!(batch.num_rows(), 0);
+        assert_eq!(
+            batch.num_columns(),
+            response.reports[0].column_header.dimensions.len()
+                + response.reports[0].get_metric_headers().len()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_metric_values() {
+        let mut response = read_response("multiple_dimensions_and_metrics.json");
+        response.reports[0].data.rows[1].metrics[0].values[0] = "many".to_string();
+
+        match report_to_record_batch(&response.reports[0], 0) {
+            Err(FlattenError::InvalidMetricValue { location, value }) => {
+                assert_eq!(location.row, 1);
+                assert_eq!(location.column, Some("ga:sessions".to_string()));
+                assert_eq!(value, "many");
+            }
+            other => panic!("expected an invalid metric value, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn empty_cells_are_null() {
+        let response = read_response("multiple_dimensions_and_metrics.json");
+        let mut table =
+            FlatTable::from_report(&response.reports[0], 0, &Default::default()).unwrap();
+        table.rows[1][0] = Cell::Empty;
+        table.rows[1][2] = Cell::Empty;
+
+        let batch = table_to_record_batch(&table, 0).unwrap();
+
+        assert!(batch
+            .schema()
+            .fields()
+            .iter()
+            .all(|field| field.is_nullable()));
+        assert!(batch.column(0).is_null(1));
+        assert!(batch.column(2).is_null(1));
+        assert!(!batch.column(2).is_null(0));
+    }
+
+    #[test]
+    fn ipc_round_trip() {
+        let response = read_response("multiple_reports.json");
+        let batches = response_to_record_batches(&response).unwrap();
+        assert_eq!(batches.len(), 2);
+
+        let mut file: Vec<u8> = Vec::new();
+        write_ipc_file(&batches[1], &mut file).unwrap();
+        let read_back: Vec<RecordBatch> = FileReader::try_new(Cursor::new(file), None)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read_back, vec![batches[1].clone()]);
+
+        let mut stream: Vec<u8> = Vec::new();
+        write_ipc_stream(&batches[0], &mut stream).unwrap();
+        let read_back: Vec<RecordBatch> = StreamReader::try_new(Cursor::new(stream), None)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read_back, vec![batches[0].clone()]);
+    }
+}
@@ -1,55 +1,629 @@
+use crate::comparison::Comparison;
+use crate::error::FlattenError;
+use crate::flat_table::{Cell, Column, ColumnKind, FlatTable, FlatTableOptions, RowFlattener};
+use crate::streaming::{stream_report_response, RowHandler};
 use crate::types::*;
-use itertools::Itertools;
+use std::io::{self, BufWriter, Read, Write};
+
+/// Controls when text fields (headers and dimension values) are wrapped in quotes.
+///
+/// Quoting follows [RFC 4180](https://tools.ietf.org/html/rfc4180): embedded quotes are
+/// doubled, and any field containing the delimiter, a quote, or a line break is always
+/// quoted. Metric values are only quoted when one of those characters requires it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuoteStyle {
+    /// Quote every header and dimension value
+    #[default]
+    Always,
+    /// Quote only the fields that contain the delimiter, a quote, or a line break
+    Necessary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineTerminator {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineTerminator {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineTerminator::Lf => "\n",
+            LineTerminator::CrLf => "\r\n",
+        }
+    }
+}
+
+/// Formatting options for delimited output.
+///
+/// The default matches `to_delimited` with a comma delimiter: quoted headers and
+/// dimensions, `\n` line endings, a header line, no byte order mark, a trailing newline,
+/// no summary rows or metadata comments, and the wide layout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DelimitedOptions {
+    pub delimiter: String,
+    pub quote_style: QuoteStyle,
+    pub quote_char: char,
+    pub line_terminator: LineTerminator,
+    pub include_header: bool,
+    /// Start the output with a UTF-8 byte order mark, which Excel uses to detect the encoding.
+    /// Each report in a `Vec<String>` or written to its own writer gets one, reports written
+    /// one after another to the same writer share a single mark.
+    pub byte_order_mark: bool,
+    pub trailing_newline: bool,
+    /// Append a `TOTAL` row with the report's totals. The `TOTAL`, `MIN` and `MAX` labels go
+    /// in the first dimension column, so reports without dimensions can't have these rows.
+    pub include_totals: bool,
+    /// Append a `MIN` row with the report's minimums, when the report has them
+    pub include_minimums: bool,
+    /// Append a `MAX` row with the report's maximums, when the report has them
+    pub include_maximums: bool,
+    /// Append `#`-prefixed comment lines with the report's sampling and paging metadata
+    pub include_metadata_comments: bool,
+    pub layout: Layout,
+    /// Names for each report's date ranges, in report order, used instead of the `_2`
+    /// suffixes in metric headers and the numbers in the long layout's `date_range` column.
+    /// Reports without labels keep the defaults.
+    pub date_range_labels: Vec<Vec<String>>,
+    /// Append columns comparing each date range to a base date range, see `Comparison`.
+    /// Only available in the wide layout.
+    pub comparison: Option<Comparison>,
+}
+
+impl Default for DelimitedOptions {
+    fn default() -> Self {
+        DelimitedOptions {
+            delimiter: ",".to_string(),
+            quote_style: QuoteStyle::Always,
+            quote_char: '"',
+            line_terminator: LineTerminator::Lf,
+            include_header: true,
+            byte_order_mark: false,
+            trailing_newline: true,
+            include_totals: false,
+            include_minimums: false,
+            include_maximums: false,
+            include_metadata_comments: false,
+            layout: Layout::Wide,
+            date_range_labels: Vec::new(),
+            comparison: None,
+        }
+    }
+}
+
+const BYTE_ORDER_MARK: &str = "\u{feff}";
+
+pub fn response_to_delimited_reports(
+    response: &ReportResponse,
+    options: &DelimitedOptions,
+) -> Result<Vec<String>, FlattenError> {
+    check_options(options)?;
 
-pub fn response_to_delimited_reports(response: &ReportResponse, delimiter: &str) -> Vec<String> {
     response
         .reports
         .iter()
-        .map(|report| report_to_flat(&report, delimiter))
+        .enumerate()
+        .map(|(report_index, report)| report_to_flat(report, report_index, options))
         .collect()
 }
 
-fn report_to_flat(report: &Report, delimiter: &str) -> String {
-    let dimension_header_iter = report
-        .column_header
-        .dimensions
-        .iter()
-        .map(|entry| format!("\"{}\"", entry));
+/// Writes every report in the response to `writer`, one after another.
+pub fn write_delimited_reports<W: Write>(
+    response: &ReportResponse,
+    writer: &mut W,
+    options: &DelimitedOptions,
+) -> Result<(), FlattenError> {
+    check_options(options)?;
 
-    let metric_headers = report.get_metric_headers();
+    let table_options = options.table_options();
+    let mut writer = BufWriter::new(writer);
+    let mut output = OutputPosition::default();
+    for (report_index, report) in response.reports.iter().enumerate() {
+        write_report(
+            report,
+            report_index,
+            &mut writer,
+            &mut output,
+            options,
+            &table_options,
+        )?;
+    }
+    writer.flush()?;
 
-    let metric_header_iter = metric_headers
-        .iter()
-        .map(|entry: &MetricHeaderEntry| format!("\"{}\"", entry.name));
+    Ok(())
+}
 
-    let mut result = format!(
-        "{}\n",
-        dimension_header_iter
-            .chain(metric_header_iter)
-            .join(delimiter)
-            .to_string()
-    );
+/// Writes each report in the response to its own writer, obtained by calling
+/// `writer_for_report` with the report's index.
+pub fn write_delimited_reports_with<W, F>(
+    response: &ReportResponse,
+    options: &DelimitedOptions,
+    mut writer_for_report: F,
+) -> Result<(), FlattenError>
+where
+    W: Write,
+    F: FnMut(usize) -> io::Result<W>,
+{
+    check_options(options)?;
 
-    report.data.rows.iter().for_each(|report_row| {
-        if !report_row.dimensions.is_empty() {
-            result.push_str(
-                report_row
-                    .dimensions
-                    .iter()
-                    .map(|entry| format!("\"{}\"", entry))
-                    .join(delimiter)
-                    .to_string()
-                    .as_str(),
-            );
-            result.push_str(delimiter);
-        };
+    let table_options = options.table_options();
+    for (report_index, report) in response.reports.iter().enumerate() {
+        let mut writer = BufWriter::new(writer_for_report(report_index)?);
+        write_report(
+            report,
+            report_index,
+            &mut writer,
+            &mut OutputPosition::default(),
+            options,
+            &table_options,
+        )?;
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+// a quote character that can also end a line or a field can't be escaped by doubling
+pub(crate) fn check_options(options: &DelimitedOptions) -> Result<(), FlattenError> {
+    let quote_char = options.quote_char;
+    if quote_char == '\n' || quote_char == '\r' || options.delimiter.contains(quote_char) {
+        return Err(FlattenError::UnsupportedFeature(format!(
+            "quote character {:?} cannot be used with delimiter {:?}",
+            quote_char, options.delimiter
+        )));
+    }
+
+    options.table_options().check()
+}
+
+impl DelimitedOptions {
+    fn includes_summaries(&self) -> bool {
+        self.include_totals || self.include_minimums || self.include_maximums
+    }
+
+    pub(crate) fn table_options(&self) -> FlatTableOptions {
+        FlatTableOptions {
+            layout: self.layout,
+            date_range_labels: self.date_range_labels.clone(),
+            comparison: self.comparison,
+        }
+    }
+}
+
+fn report_to_flat(
+    report: &Report,
+    report_index: usize,
+    options: &DelimitedOptions,
+) -> Result<String, FlattenError> {
+    let mut result: Vec<u8> = Vec::new();
+
+    write_report(
+        report,
+        report_index,
+        &mut result,
+        &mut OutputPosition::default(),
+        options,
+        &options.table_options(),
+    )?;
+
+    Ok(String::from_utf8(result).expect("delimited output is only written from strings"))
+}
+
+fn write_report<W: Write>(
+    report: &Report,
+    report_index: usize,
+    writer: &mut W,
+    output: &mut OutputPosition,
+    options: &DelimitedOptions,
+    table_options: &FlatTableOptions,
+) -> Result<(), FlattenError> {
+    if options.includes_summaries() {
+        report.check_summaries(report_index)?;
+    }
+    let table = FlatTable::from_report(report, report_index, table_options)?;
+
+    write_table(&table, report_index, writer, output, options)
+}
+
+// writes an already flattened table, the `report_index`th in its output
+pub(crate) fn write_table<W: Write>(
+    table: &FlatTable,
+    report_index: usize,
+    writer: &mut W,
+    output: &mut OutputPosition,
+    options: &DelimitedOptions,
+) -> Result<(), FlattenError> {
+    let mut report_writer = ReportWriter::start(writer, output, options)?;
+
+    report_writer.write_header(writer, &table.columns, report_index)?;
+    for cells in table.rows.iter() {
+        report_writer.write_cells(writer, &table.columns, cells, None)?;
+    }
+    report_writer.write_summaries(
+        writer,
+        &table.columns,
+        [&table.totals, &table.minimums, &table.maximums],
+    )?;
+
+    report_writer.finish(writer, output, &table.metadata)
+}
+
+/// Streams every report in the response read from `reader` to `writer`, one row at a time.
+pub fn write_delimited_reports_from_reader<R: Read, W: Write>(
+    reader: R,
+    writer: &mut W,
+    options: &DelimitedOptions,
+) -> Result<(), FlattenError> {
+    check_options(options)?;
+
+    let mut handler = DelimitedRowHandler {
+        writer: BufWriter::new(writer),
+        options,
+        table_options: options.table_options(),
+        output: OutputPosition::default(),
+        current_report: None,
+    };
+    stream_report_response(reader, &mut handler)?;
+    handler.writer.flush()?;
+
+    Ok(())
+}
+
+struct DelimitedRowHandler<'o, W> {
+    writer: W,
+    options: &'o DelimitedOptions,
+    table_options: FlatTableOptions,
+    output: OutputPosition,
+    current_report: Option<StreamedReport<'o>>,
+}
+
+struct StreamedReport<'o> {
+    column_header: ColumnHeader,
+    report_writer: ReportWriter<'o>,
+    // the number of date ranges, and so the columns, aren't known until
+    // the first row has been seen
+    flattener: Option<RowFlattener>,
+    rows_written: usize,
+}
+
+impl<'o> StreamedReport<'o> {
+    // starts the table, writing the header, unless that has already been done
+    fn table<W: Write>(
+        &mut self,
+        writer: &mut W,
+        report_index: usize,
+        number_of_date_ranges: usize,
+        table_options: &FlatTableOptions,
+    ) -> Result<(&RowFlattener, &mut ReportWriter<'o>), FlattenError> {
+        if self.flattener.is_none() {
+            let flattener = RowFlattener::new(
+                &self.column_header,
+                &ReportSource::default(),
+                report_index,
+                number_of_date_ranges,
+                table_options,
+            )?;
+            self.report_writer
+                .write_header(writer, flattener.columns(), report_index)?;
+            self.flattener = Some(flattener);
+        }
+
+        Ok((self.flattener.as_ref().unwrap(), &mut self.report_writer))
+    }
+}
+
+impl<'o, W: Write> RowHandler for DelimitedRowHandler<'o, W> {
+    fn start_report(
+        &mut self,
+        _report_index: usize,
+        column_header: &ColumnHeader,
+    ) -> Result<(), FlattenError> {
+        self.current_report = Some(StreamedReport {
+            column_header: column_header.clone(),
+            report_writer: ReportWriter::start(&mut self.writer, &mut self.output, self.options)?,
+            flattener: None,
+            rows_written: 0,
+        });
+
+        Ok(())
+    }
+
+    fn row(&mut self, report_index: usize, row: ReportRow) -> Result<(), FlattenError> {
+        let streamed = self
+            .current_report
+            .as_mut()
+            .expect("rows are only handled after their report has started");
+        let writer = &mut self.writer;
+        let row_index = streamed.rows_written;
 
-        let metric_data = report_row.flat_value_iterator().join(delimiter).to_string();
+        let (flattener, report_writer) =
+            streamed.table(writer, report_index, row.metrics.len(), &self.table_options)?;
+        flattener.flatten_row(&row, row_index, |cells| {
+            report_writer.write_cells(writer, flattener.columns(), &cells, None)?;
+            Ok(())
+        })?;
+        streamed.rows_written += 1;
+
+        Ok(())
+    }
 
-        result.push_str(format!("{}\n", metric_data).as_str());
-    });
+    fn end_report(&mut self, report_index: usize, report: &Report) -> Result<(), FlattenError> {
+        let mut streamed = self
+            .current_report
+            .take()
+            .expect("reports are only ended after they have started");
+        let writer = &mut self.writer;
+        let summary_row_index = streamed.rows_written;
 
-    result
+        // a report without rows has as many date ranges as totals
+        let (flattener, report_writer) = streamed.table(
+            writer,
+            report_index,
+            report.data.totals.len(),
+            &self.table_options,
+        )?;
+        let [totals, minimums, maximums] =
+            flattener.flatten_summaries(&report.data, summary_row_index)?;
+        report_writer.write_summaries(
+            writer,
+            flattener.columns(),
+            [&totals, &minimums, &maximums],
+        )?;
+
+        streamed
+            .report_writer
+            .finish(writer, &mut self.output, &report.metadata())
+    }
+}
+
+// where the next report starts in an output that reports are written to one
+// after another: the byte order mark only goes at the very start, and a report
+// that ended without a trailing newline still needs one before the next
+#[derive(Default)]
+pub(crate) struct OutputPosition {
+    started: bool,
+    mid_line: bool,
+}
+
+impl OutputPosition {
+    pub(crate) fn start<W: Write>(
+        &mut self,
+        writer: &mut W,
+        options: &DelimitedOptions,
+    ) -> io::Result<()> {
+        if !self.started && options.byte_order_mark {
+            writer.write_all(BYTE_ORDER_MARK.as_bytes())?;
+        }
+        if self.mid_line {
+            writer.write_all(options.line_terminator.as_str().as_bytes())?;
+        }
+        self.started = true;
+        self.mid_line = false;
+
+        Ok(())
+    }
+}
+
+// writes a single flattened report a line at a time
+struct ReportWriter<'o> {
+    options: &'o DelimitedOptions,
+    lines_written: usize,
+}
+
+impl<'o> ReportWriter<'o> {
+    fn start<W: Write>(
+        writer: &mut W,
+        output: &mut OutputPosition,
+        options: &'o DelimitedOptions,
+    ) -> io::Result<Self> {
+        output.start(writer, options)?;
+
+        Ok(ReportWriter {
+            options,
+            lines_written: 0,
+        })
+    }
+
+    // summary rows are only told apart from data rows by their label in the first
+    // dimension column, so a report without dimensions can't have them
+    fn write_header<W: Write>(
+        &mut self,
+        writer: &mut W,
+        columns: &[Column],
+        report_index: usize,
+    ) -> Result<(), FlattenError> {
+        if self.options.includes_summaries()
+            && columns.first().map(|column| column.kind) != Some(ColumnKind::Dimension)
+        {
+            return Err(FlattenError::UnsupportedFeature(format!(
+                "report {} has no dimensions to label its TOTAL, MIN and MAX rows with",
+                report_index
+            )));
+        }
+
+        if !self.options.include_header {
+            return Ok(());
+        }
+
+        self.start_line(writer)?;
+        write_fields(
+            writer,
+            columns.iter().map(|column| &column.name),
+            self.options.quote_style,
+            self.options,
+        )?;
+
+        Ok(())
+    }
+
+    // summary rows are labelled in the first column, which write_header made sure
+    // is a dimension
+    fn write_cells<W: Write>(
+        &mut self,
+        writer: &mut W,
+        columns: &[Column],
+        cells: &[Cell],
+        label: Option<&str>,
+    ) -> io::Result<()> {
+        self.start_line(writer)?;
+
+        let options = self.options;
+        for (i, (column, cell)) in columns.iter().zip(cells.iter()).enumerate() {
+            if i > 0 {
+                writer.write_all(options.delimiter.as_bytes())?;
+            }
+
+            match (cell, label) {
+                (_, Some(label)) if i == 0 => {
+                    write_escaped(writer, label, options.quote_style, options)?
+                }
+                (Cell::Text(text), _) => write_escaped(writer, text, options.quote_style, options)?,
+                (Cell::Metric { text, .. }, _) => {
+                    write_escaped(writer, text, QuoteStyle::Necessary, options)?
+                }
+                (Cell::Number(number), _) => writer.write_all(number.to_string().as_bytes())?,
+                // empty dimensions are quoted like any other
+                (Cell::Empty, _) if column.kind == ColumnKind::Dimension => {
+                    write_escaped(writer, "", options.quote_style, options)?
+                }
+                (Cell::Empty, _) => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    // totals, minimums and maximums, in the order FlatTable has them
+    fn write_summaries<W: Write>(
+        &mut self,
+        writer: &mut W,
+        columns: &[Column],
+        summaries: [&Option<Vec<Vec<Cell>>>; 3],
+    ) -> io::Result<()> {
+        let options = self.options;
+        let [totals, minimums, maximums] = summaries;
+        let labelled_summaries = [
+            (options.include_totals, "TOTAL", totals),
+            (options.include_minimums, "MIN", minimums),
+            (options.include_maximums, "MAX", maximums),
+        ];
+
+        for (include, label, summary) in labelled_summaries.iter() {
+            if let (true, Some(rows)) = (include, summary) {
+                for cells in rows.iter() {
+                    self.write_cells(writer, columns, cells, Some(label))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish<W: Write>(
+        mut self,
+        writer: &mut W,
+        output: &mut OutputPosition,
+        metadata: &ReportMetadata,
+    ) -> Result<(), FlattenError> {
+        if self.options.include_metadata_comments {
+            for line in metadata_comment_lines(metadata) {
+                self.start_line(writer)?;
+                writer.write_all(line.as_bytes())?;
+            }
+        }
+
+        if self.options.trailing_newline && self.lines_written > 0 {
+            writer.write_all(self.options.line_terminator.as_str().as_bytes())?;
+        }
+        output.mid_line = !self.options.trailing_newline && self.lines_written > 0;
+
+        Ok(())
+    }
+
+    fn start_line<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        if self.lines_written > 0 {
+            writer.write_all(self.options.line_terminator.as_str().as_bytes())?;
+        }
+        self.lines_written += 1;
+
+        Ok(())
+    }
+}
+
+// metadata is only known once a streamed report's data has been read, so the
+// comment lines go after the rows rather than before the header
+fn metadata_comment_lines(metadata: &ReportMetadata) -> Vec<String> {
+    let mut lines = vec![format!("# isSampled: {}", metadata.is_sampled)];
+
+    if let Some(is_data_golden) = metadata.is_data_golden {
+        lines.push(format!("# isDataGolden: {}", is_data_golden));
+    }
+    if let Some(row_count) = metadata.row_count {
+        lines.push(format!("# rowCount: {}", row_count));
+    }
+    if let Some(samples_read_counts) = &metadata.samples_read_counts {
+        lines.push(format!(
+            "# samplesReadCounts: {}",
+            samples_read_counts.join(",")
+        ));
+    }
+    if let Some(sampling_space_sizes) = &metadata.sampling_space_sizes {
+        lines.push(format!(
+            "# samplingSpaceSizes: {}",
+            sampling_space_sizes.join(",")
+        ));
+    }
+    if let Some(next_page_token) = &metadata.next_page_token {
+        lines.push(format!("# nextPageToken: {}", next_page_token));
+    }
+
+    lines
+}
+
+fn write_fields<'a, W: Write>(
+    writer: &mut W,
+    values: impl Iterator<Item = &'a String>,
+    quote_style: QuoteStyle,
+    options: &DelimitedOptions,
+) -> io::Result<()> {
+    for (i, value) in values.enumerate() {
+        if i > 0 {
+            writer.write_all(options.delimiter.as_bytes())?;
+        }
+        write_escaped(writer, value, quote_style, options)?;
+    }
+
+    Ok(())
+}
+
+fn write_escaped<W: Write>(
+    writer: &mut W,
+    value: &str,
+    quote_style: QuoteStyle,
+    options: &DelimitedOptions,
+) -> io::Result<()> {
+    let quote_char = options.quote_char;
+    let needs_quotes = quote_style == QuoteStyle::Always
+        || (!options.delimiter.is_empty() && value.contains(options.delimiter.as_str()))
+        || value.contains([quote_char, '\n', '\r']);
+
+    if !needs_quotes {
+        return writer.write_all(value.as_bytes());
+    }
+
+    let mut quote_buffer = [0; 4];
+    let quote = quote_char.encode_utf8(&mut quote_buffer).as_bytes();
+
+    writer.write_all(quote)?;
+    for (i, part) in value.split(quote_char).enumerate() {
+        if i > 0 {
+            writer.write_all(quote)?;
+            writer.write_all(quote)?;
+        }
+        writer.write_all(part.as_bytes())?;
+    }
+    writer.write_all(quote)
 }
 
 #[cfg(test)]
@@ -70,7 +644,8 @@ mod tests {
         let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
 
         assert_eq!(
-            response_to_delimited_reports(&deserialized_response, ","),
+            response_to_delimited_reports(&deserialized_response, &DelimitedOptions::default())
+                .unwrap(),
             vec!["\"ga:deviceCategory\",\"ga:sessions\"\n"]
         )
     }
@@ -85,7 +660,12 @@ mod tests {
         let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
 
         assert_eq!(
-            report_to_flat(&deserialized_response.reports[0], ","),
+            report_to_flat(
+                &deserialized_response.reports[0],
+                0,
+                &DelimitedOptions::default()
+            )
+            .unwrap(),
             "\"ga:sessions\"\n44\n".to_string()
         )
     }
@@ -101,7 +681,15 @@ mod tests {
         let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
 
         assert_eq!(
-            report_to_flat(&deserialized_response.reports[0], "|delimiter|"),
+            report_to_flat(
+                &deserialized_response.reports[0],
+                0,
+                &DelimitedOptions {
+                    delimiter: "|delimiter|".to_string(),
+                    ..Default::default()
+                }
+            )
+            .unwrap(),
             indoc!(
                 r#""ga:deviceCategory"|delimiter|"ga:sessions"
                 "desktop"|delimiter|43
@@ -123,7 +711,12 @@ mod tests {
         let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
 
         assert_eq!(
-            report_to_flat(&deserialized_response.reports[0], ","),
+            report_to_flat(
+                &deserialized_response.reports[0],
+                0,
+                &DelimitedOptions::default()
+            )
+            .unwrap(),
             indoc!(
                 r#""ga:deviceCategory","ga:country","ga:sessions","ga:bounces"
                 "desktop","Australia",1,1
@@ -145,7 +738,13 @@ mod tests {
 
         let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
 
-        assert!(report_to_flat(&deserialized_response.reports[0], ",").len() > 0)
+        assert!(!report_to_flat(
+            &deserialized_response.reports[0],
+            0,
+            &DelimitedOptions::default()
+        )
+        .unwrap()
+        .is_empty())
     }
 
     #[test]
@@ -158,7 +757,8 @@ mod tests {
         let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
 
         assert_eq!(
-            response_to_delimited_reports(&deserialized_response, ","),
+            response_to_delimited_reports(&deserialized_response, &DelimitedOptions::default())
+                .unwrap(),
             vec![
                 indoc!(
                     r#""ga:deviceCategory","ga:sessions","ga:bounces"
@@ -191,7 +791,7 @@ mod tests {
         let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
 
         assert_eq!(
-            response_to_delimited_reports(&deserialized_response, ","),
+            response_to_delimited_reports(&deserialized_response, &DelimitedOptions::default()).unwrap(),
             vec![
                 indoc!(
                     r#""ga:browser","ga:avgTimeOnPage","ga:pageviewsPerSession","ga:avgTimeOnPage_2","ga:pageviewsPerSession_2"
@@ -204,6 +804,130 @@ mod tests {
         )
     }
 
+    #[test]
+    fn long_layout() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/multiple_date_ranges.json"),
+        )
+        .unwrap();
+
+        let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        let options = DelimitedOptions {
+            layout: Layout::Long,
+            include_totals: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            response_to_delimited_reports(&deserialized_response, &options).unwrap(),
+            vec![indoc!(
+                r#""ga:browser","date_range","ga:avgTimeOnPage","ga:pageviewsPerSession"
+                    "Chrome",1,108.1733,2.93126
+                    "Chrome",2,129.7071651,3.60975609
+                    "Edge",1,51.794117,6.6666667
+                    "Edge",2,210.866667,2.875
+                    "Firefox",1,123.657142,2.09375
+                    "Firefox",2,75.333333,1.5
+                    "TOTAL",1,89.31735436893204,3.4342688330871494
+                    "TOTAL",2,155.70253164556962,3.0343347639484977
+                    "#
+            )
+            .to_string()]
+        )
+    }
+
+    #[test]
+    fn labelled_date_ranges() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/multiple_date_ranges.json"),
+        )
+        .unwrap();
+
+        let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        let options = DelimitedOptions {
+            date_range_labels: vec![vec!["current".to_string(), "previous".to_string()]],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            response_to_delimited_reports(&deserialized_response, &options).unwrap(),
+            vec![indoc!(
+                r#""ga:browser","ga:avgTimeOnPage[current]","ga:pageviewsPerSession[current]","ga:avgTimeOnPage[previous]","ga:pageviewsPerSession[previous]"
+                    "Chrome",108.1733,2.93126,129.7071651,3.60975609
+                    "Edge",51.794117,6.6666667,210.866667,2.875
+                    "Firefox",123.657142,2.09375,75.333333,1.5
+                    "#
+            )
+            .to_string()]
+        );
+
+        let options = DelimitedOptions {
+            layout: Layout::Long,
+            quote_style: QuoteStyle::Necessary,
+            ..options
+        };
+
+        assert_eq!(
+            response_to_delimited_reports(&deserialized_response, &options).unwrap(),
+            vec![indoc!(
+                r#"ga:browser,date_range,ga:avgTimeOnPage,ga:pageviewsPerSession
+                    Chrome,current,108.1733,2.93126
+                    Chrome,previous,129.7071651,3.60975609
+                    Edge,current,51.794117,6.6666667
+                    Edge,previous,210.866667,2.875
+                    Firefox,current,123.657142,2.09375
+                    Firefox,previous,75.333333,1.5
+                    "#
+            )
+            .to_string()]
+        );
+    }
+
+    #[test]
+    fn compares_date_ranges() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/multiple_date_ranges.json"),
+        )
+        .unwrap();
+
+        let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        let options = DelimitedOptions {
+            comparison: Some(Comparison::default()),
+            include_totals: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            response_to_delimited_reports(&deserialized_response, &options).unwrap(),
+            vec![indoc!(
+                r#""ga:browser","ga:avgTimeOnPage","ga:pageviewsPerSession","ga:avgTimeOnPage_2","ga:pageviewsPerSession_2","ga:avgTimeOnPage_2_delta","ga:avgTimeOnPage_2_pct_change","ga:pageviewsPerSession_2_delta","ga:pageviewsPerSession_2_pct_change"
+                    "Chrome",108.1733,2.93126,129.7071651,3.60975609,21.5338651,19.906820906822663,0.6784960899999999,23.14690917898787
+                    "Edge",51.794117,6.6666667,210.866667,2.875,159.07255,307.1247454609565,-3.7916667000000004,-56.87500021562501
+                    "Firefox",123.657142,2.09375,75.333333,1.5,-48.323809,-39.07886614426201,-0.59375,-28.35820895522388
+                    "TOTAL",89.31735436893204,3.4342688330871494,155.70253164556962,3.0343347639484977,66.38517727663758,74.32506005767772,-0.3999340691386517,-11.645392034703965
+                    "#
+            )
+            .to_string()]
+        );
+    }
+
+    #[test]
+    fn rejects_comparisons_in_the_long_layout() {
+        let options = DelimitedOptions {
+            comparison: Some(Comparison::default()),
+            layout: Layout::Long,
+            ..Default::default()
+        };
+
+        match response_to_delimited_reports(&ReportResponse { reports: vec![] }, &options) {
+            Err(FlattenError::UnsupportedFeature(_)) => (),
+            other => panic!("expected an unsupported feature, got {:?}", other),
+        }
+    }
+
     #[test]
     fn includes_segment_as_a_dimension() {
         let data: String = fs::read_to_string(
@@ -214,7 +938,8 @@ mod tests {
         let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
 
         assert_eq!(
-            response_to_delimited_reports(&deserialized_response, ","),
+            response_to_delimited_reports(&deserialized_response, &DelimitedOptions::default())
+                .unwrap(),
             vec![indoc!(
                 r#""ga:browser","ga:segment","ga:sessions"
                     "Android Webview","Returning Users",1
@@ -228,4 +953,503 @@ mod tests {
             .to_string()]
         )
     }
+
+    #[test]
+    fn pivot() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/pivot.json"),
+        )
+        .unwrap();
+
+        let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        assert_eq!(
+            response_to_delimited_reports(&deserialized_response, &DelimitedOptions::default())
+                .unwrap(),
+            vec![indoc!(
+                r#""ga:deviceCategory","ga:users","pivot1_201831_ga:sessions","pivot1_201832_ga:sessions"
+                "desktop",38,43,12
+                "mobile",1,1,0
+                "#
+            )
+            .to_string()]
+        )
+    }
+
+    #[test]
+    fn escapes_special_characters() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/special_characters.json"),
+        )
+        .unwrap();
+
+        let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        assert_eq!(
+            response_to_delimited_reports(&deserialized_response, &DelimitedOptions::default())
+                .unwrap(),
+            vec![indoc!(
+                r#""ga:pageTitle","ga:pageviews"
+                "Say ""hi""",12
+                "Hello, world",7
+                "Line one
+                Line two",3
+                "plain",1
+                "#
+            )
+            .to_string()]
+        )
+    }
+
+    #[test]
+    fn quotes_only_when_necessary() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/special_characters.json"),
+        )
+        .unwrap();
+
+        let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        assert_eq!(
+            response_to_delimited_reports(
+                &deserialized_response,
+                &DelimitedOptions {
+                    quote_style: QuoteStyle::Necessary,
+                    ..Default::default()
+                }
+            )
+            .unwrap(),
+            vec![indoc!(
+                r#"ga:pageTitle,ga:pageviews
+                "Say ""hi""",12
+                "Hello, world",7
+                "Line one
+                Line two",3
+                plain,1
+                "#
+            )
+            .to_string()]
+        )
+    }
+
+    #[test]
+    fn quotes_values_containing_the_delimiter() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/special_characters.json"),
+        )
+        .unwrap();
+
+        let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        assert_eq!(
+            response_to_delimited_reports(
+                &deserialized_response,
+                &DelimitedOptions {
+                    delimiter: "a".to_string(),
+                    quote_style: QuoteStyle::Necessary,
+                    ..Default::default()
+                }
+            )
+            .unwrap(),
+            vec![indoc!(
+                r#""ga:pageTitle"a"ga:pageviews"
+                "Say ""hi"""a12
+                Hello, worlda7
+                "Line one
+                Line two"a3
+                "plain"a1
+                "#
+            )
+            .to_string()]
+        )
+    }
+
+    #[test]
+    fn custom_quote_char() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/special_characters.json"),
+        )
+        .unwrap();
+
+        let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        assert_eq!(
+            response_to_delimited_reports(
+                &deserialized_response,
+                &DelimitedOptions {
+                    quote_char: '\'',
+                    ..Default::default()
+                }
+            )
+            .unwrap(),
+            vec![indoc!(
+                r#"'ga:pageTitle','ga:pageviews'
+                'Say "hi"',12
+                'Hello, world',7
+                'Line one
+                Line two',3
+                'plain',1
+                "#
+            )
+            .to_string()]
+        )
+    }
+
+    #[test]
+    fn crlf_with_byte_order_mark() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("test_reports/single_dimension_and_metric.json"),
+        )
+        .unwrap();
+
+        let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        assert_eq!(
+            response_to_delimited_reports(
+                &deserialized_response,
+                &DelimitedOptions {
+                    line_terminator: LineTerminator::CrLf,
+                    byte_order_mark: true,
+                    ..Default::default()
+                }
+            ).unwrap(),
+            vec!["\u{feff}\"ga:deviceCategory\",\"ga:sessions\"\r\n\"desktop\",43\r\n\"mobile\",1\r\n"]
+        )
+    }
+
+    #[test]
+    fn headerless_without_trailing_newline() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("test_reports/single_dimension_and_metric.json"),
+        )
+        .unwrap();
+
+        let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        assert_eq!(
+            response_to_delimited_reports(
+                &deserialized_response,
+                &DelimitedOptions {
+                    include_header: false,
+                    trailing_newline: false,
+                    ..Default::default()
+                }
+            )
+            .unwrap(),
+            vec!["\"desktop\",43\n\"mobile\",1"]
+        )
+    }
+
+    #[test]
+    fn headerless_no_rows() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/no_rows.json"),
+        )
+        .unwrap();
+
+        let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        assert_eq!(
+            response_to_delimited_reports(
+                &deserialized_response,
+                &DelimitedOptions {
+                    include_header: false,
+                    ..Default::default()
+                }
+            )
+            .unwrap(),
+            vec![""]
+        )
+    }
+
+    #[test]
+    fn write_multiple_reports_to_one_writer() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/multiple_reports.json"),
+        )
+        .unwrap();
+
+        let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        let mut output: Vec<u8> = Vec::new();
+        write_delimited_reports(
+            &deserialized_response,
+            &mut output,
+            &DelimitedOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            response_to_delimited_reports(&deserialized_response, &DelimitedOptions::default())
+                .unwrap()
+                .concat()
+        )
+    }
+
+    #[test]
+    fn reports_in_one_writer_share_a_byte_order_mark_and_stay_on_separate_lines() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/multiple_reports.json");
+        let data: String = fs::read_to_string(&path).unwrap();
+        let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+        let options = DelimitedOptions {
+            byte_order_mark: true,
+            trailing_newline: false,
+            ..Default::default()
+        };
+
+        let reports = response_to_delimited_reports(&deserialized_response, &options).unwrap();
+        let expected = format!("{}\n{}", reports[0], &reports[1][BYTE_ORDER_MARK.len()..]);
+        assert_eq!(expected.matches(BYTE_ORDER_MARK).count(), 1);
+        assert!(!expected.ends_with('\n'));
+
+        let mut output: Vec<u8> = Vec::new();
+        write_delimited_reports(&deserialized_response, &mut output, &options).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+
+        let mut streamed: Vec<u8> = Vec::new();
+        write_delimited_reports_from_reader(
+            fs::File::open(&path).unwrap(),
+            &mut streamed,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(streamed).unwrap(), expected);
+    }
+
+    #[test]
+    fn write_each_report_to_its_own_writer() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/multiple_reports.json"),
+        )
+        .unwrap();
+
+        let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        let mut outputs: Vec<Vec<u8>> = vec![Vec::new(), Vec::new()];
+        let mut output_iter = outputs.iter_mut();
+        let mut requested_indexes: Vec<usize> = Vec::new();
+        write_delimited_reports_with(
+            &deserialized_response,
+            &DelimitedOptions::default(),
+            |report_index| {
+                requested_indexes.push(report_index);
+                Ok(output_iter.next().unwrap())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(requested_indexes, vec![0, 1]);
+        assert_eq!(
+            outputs
+                .into_iter()
+                .map(|output| String::from_utf8(output).unwrap())
+                .collect::<Vec<String>>(),
+            response_to_delimited_reports(&deserialized_response, &DelimitedOptions::default())
+                .unwrap()
+        )
+    }
+
+    #[test]
+    fn streamed_output_matches_parsed_output() {
+        let fixtures = [
+            "no_rows.json",
+            "no_dimensions.json",
+            "single_dimension_and_metric.json",
+            "multiple_dimensions_and_metrics.json",
+            "multiple_reports.json",
+            "multiple_date_ranges.json",
+            "including_segment.json",
+            "pivot.json",
+            "special_characters.json",
+            "sampled.json",
+            "large_report.json",
+        ];
+        for layout in [Layout::Wide, Layout::Long].iter() {
+            for fixture in fixtures.iter() {
+                let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                    .join("test_reports")
+                    .join(fixture);
+                let data: String = fs::read_to_string(&path).unwrap();
+                let deserialized_response: ReportResponse =
+                    serde_json::from_str(data.as_str()).unwrap();
+                // summary rows need a dimension column to be labelled in
+                let include_summaries = deserialized_response
+                    .reports
+                    .iter()
+                    .all(|report| !report.column_header.dimensions.is_empty());
+                let options = DelimitedOptions {
+                    byte_order_mark: true,
+                    include_totals: include_summaries,
+                    include_minimums: include_summaries,
+                    include_maximums: include_summaries,
+                    include_metadata_comments: true,
+                    layout: *layout,
+                    ..Default::default()
+                };
+
+                let mut streamed: Vec<u8> = Vec::new();
+                write_delimited_reports_from_reader(
+                    fs::File::open(&path).unwrap(),
+                    &mut streamed,
+                    &options,
+                )
+                .unwrap();
+
+                let mut parsed: Vec<u8> = Vec::new();
+                write_delimited_reports(&deserialized_response, &mut parsed, &options).unwrap();
+
+                assert_eq!(
+                    String::from_utf8(streamed).unwrap(),
+                    String::from_utf8(parsed).unwrap(),
+                    "{} {:?}",
+                    fixture,
+                    layout
+                )
+            }
+        }
+    }
+
+    #[test]
+    fn streamed_shape_mismatch() {
+        let data = fs::File::open(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/missing_dimension.json"),
+        )
+        .unwrap();
+
+        let mut streamed: Vec<u8> = Vec::new();
+        match write_delimited_reports_from_reader(data, &mut streamed, &DelimitedOptions::default())
+        {
+            Err(FlattenError::ShapeMismatch { location, .. }) => assert_eq!(location.row, 1),
+            other => panic!("expected a shape mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn summary_rows() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("test_reports/multiple_dimensions_and_metrics.json"),
+        )
+        .unwrap();
+
+        let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        assert_eq!(
+            response_to_delimited_reports(
+                &deserialized_response,
+                &DelimitedOptions {
+                    include_totals: true,
+                    include_minimums: true,
+                    include_maximums: true,
+                    ..Default::default()
+                }
+            )
+            .unwrap(),
+            vec![indoc!(
+                r#""ga:deviceCategory","ga:country","ga:sessions","ga:bounces"
+                "desktop","Australia",1,1
+                "desktop","France",39,21
+                "desktop","United States",3,1
+                "mobile","Brazil",1,0
+                "TOTAL","",44,23
+                "MIN","",1,0
+                "MAX","",39,21
+                "#
+            )
+            .to_string()]
+        )
+    }
+
+    #[test]
+    fn rejects_summary_rows_without_dimensions() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/no_dimensions.json");
+        let data: String = fs::read_to_string(&path).unwrap();
+        let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        for layout in [Layout::Wide, Layout::Long].iter() {
+            let options = DelimitedOptions {
+                include_totals: true,
+                include_minimums: true,
+                include_maximums: true,
+                layout: *layout,
+                ..Default::default()
+            };
+
+            match response_to_delimited_reports(&deserialized_response, &options) {
+                Err(FlattenError::UnsupportedFeature(message)) => assert_eq!(
+                    message,
+                    "report 0 has no dimensions to label its TOTAL, MIN and MAX rows with"
+                ),
+                other => panic!("expected an unsupported feature, got {:?}", other),
+            }
+            match write_delimited_reports_from_reader(
+                fs::File::open(&path).unwrap(),
+                &mut Vec::new(),
+                &options,
+            ) {
+                Err(FlattenError::UnsupportedFeature(_)) => (),
+                other => panic!("expected an unsupported feature, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn summary_rows_across_date_ranges() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/multiple_date_ranges.json"),
+        )
+        .unwrap();
+
+        let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        let result = response_to_delimited_reports(
+            &deserialized_response,
+            &DelimitedOptions {
+                include_totals: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert!(result[0].ends_with(
+            "\"TOTAL\",89.31735436893204,3.4342688330871494,155.70253164556962,3.0343347639484977\n"
+        ))
+    }
+
+    #[test]
+    fn metadata_comments() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/sampled.json"),
+        )
+        .unwrap();
+
+        let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        assert_eq!(
+            response_to_delimited_reports(
+                &deserialized_response,
+                &DelimitedOptions {
+                    include_metadata_comments: true,
+                    ..Default::default()
+                }
+            )
+            .unwrap(),
+            vec![indoc!(
+                r#""ga:deviceCategory","ga:sessions"
+                "desktop",43
+                "mobile",1
+                # isSampled: true
+                # isDataGolden: false
+                # rowCount: 3
+                # samplesReadCounts: 499630
+                # samplingSpaceSizes: 15328013
+                # nextPageToken: 2
+                "#
+            )
+            .to_string()]
+        )
+    }
 }
@@ -0,0 +1,291 @@
+use crate::error::FlattenError;
+use crate::to_arrow::report_to_record_batch;
+use crate::types::{Report, ReportResponse};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, GzipLevel, ZstdLevel};
+use parquet::errors::ParquetError;
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::{WriterProperties, DEFAULT_MAX_ROW_GROUP_SIZE};
+use std::io::Write;
+
+/// How the column chunks of a Parquet file are compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    Uncompressed,
+    #[default]
+    Snappy,
+    Gzip,
+    Zstd,
+}
+
+impl Codec {
+    fn compression(self) -> Compression {
+        match self {
+            Codec::Uncompressed => Compression::UNCOMPRESSED,
+            Codec::Snappy => Compression::SNAPPY,
+            Codec::Gzip => Compression::GZIP(GzipLevel::default()),
+            Codec::Zstd => Compression::ZSTD(ZstdLevel::default()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParquetOptions {
+    pub compression: Codec,
+    /// The most rows in each row group, at least 1
+    pub max_row_group_size: usize,
+}
+
+impl ParquetOptions {
+    // the parquet crate panics on empty row groups rather than returning an error
+    fn check(&self) -> Result<(), FlattenError> {
+        if self.max_row_group_size == 0 {
+            return Err(FlattenError::UnsupportedFeature(
+                "a max_row_group_size of 0, row groups need at least one row".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        ParquetOptions {
+            compression: Codec::default(),
+            max_row_group_size: DEFAULT_MAX_ROW_GROUP_SIZE,
+        }
+    }
+}
+
+/// The key/value metadata keys written to every file. Keys for values the report doesn't
+/// have, such as the sampling counts of unsampled data, are left out.
+pub const IS_DATA_GOLDEN_KEY: &str = "is_data_golden";
+pub const ROW_COUNT_KEY: &str = "row_count";
+pub const IS_SAMPLED_KEY: &str = "is_sampled";
+pub const SAMPLES_READ_COUNTS_KEY: &str = "samples_read_counts";
+pub const SAMPLING_SPACE_SIZES_KEY: &str = "sampling_space_sizes";
+
+/// Writes every report in the response to the writer `writer_for_report` returns for its
+/// index, see `write_parquet_report`.
+pub fn write_parquet_reports<W, F>(
+    response: &ReportResponse,
+    options: &ParquetOptions,
+    mut writer_for_report: F,
+) -> Result<(), FlattenError>
+where
+    W: Write + Send,
+    F: FnMut(usize) -> std::io::Result<W>,
+{
+    options.check()?;
+
+    for (report_index, report) in response.reports.iter().enumerate() {
+        write_parquet_report(
+            report,
+            report_index,
+            writer_for_report(report_index)?,
+            options,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Writes the report to `writer` as a Parquet file, with the columns of
+/// `to_arrow::report_to_record_batch` and the report's metadata as key/value metadata.
+pub fn write_parquet_report<W: Write + Send>(
+    report: &Report,
+    report_index: usize,
+    writer: W,
+    options: &ParquetOptions,
+) -> Result<(), FlattenError> {
+    options.check()?;
+
+    let batch = report_to_record_batch(report, report_index)?;
+
+    let properties = WriterProperties::builder()
+        .set_compression(options.compression.compression())
+        .set_max_row_group_size(options.max_row_group_size)
+        .set_key_value_metadata(Some(report_key_value_metadata(report)))
+        .build();
+
+    let mut writer = ArrowWriter::try_new(writer, batch.schema(), Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+
+    Ok(())
+}
+
+fn report_key_value_metadata(report: &Report) -> Vec<KeyValue> {
+    let metadata = report.metadata();
+
+    let mut key_values = vec![KeyValue::new(
+        IS_SAMPLED_KEY.to_string(),
+        metadata.is_sampled.to_string(),
+    )];
+    if let Some(is_data_golden) = metadata.is_data_golden {
+        key_values.push(KeyValue::new(
+            IS_DATA_GOLDEN_KEY.to_string(),
+            is_data_golden.to_string(),
+        ));
+    }
+    if let Some(row_count) = metadata.row_count {
+        key_values.push(KeyValue::new(
+            ROW_COUNT_KEY.to_string(),
+            row_count.to_string(),
+        ));
+    }
+    // one count per date range, like in the API response
+    if let Some(samples_read_counts) = metadata.samples_read_counts {
+        key_values.push(KeyValue::new(
+            SAMPLES_READ_COUNTS_KEY.to_string(),
+            samples_read_counts.join(","),
+        ));
+    }
+    if let Some(sampling_space_sizes) = metadata.sampling_space_sizes {
+        key_values.push(KeyValue::new(
+            SAMPLING_SPACE_SIZES_KEY.to_string(),
+            sampling_space_sizes.join(","),
+        ));
+    }
+
+    key_values
+}
+
+impl From<ParquetError> for FlattenError {
+    fn from(err: ParquetError) -> Self {
+        FlattenError::Parquet(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::record_batch::RecordBatch;
+    use bytes::Bytes;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+
+    fn read_response(name: &str) -> ReportResponse {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("test_reports")
+                .join(name),
+        )
+        .unwrap();
+
+        serde_json::from_str(data.as_str()).unwrap()
+    }
+
+    fn write_report(report: &Report, options: &ParquetOptions) -> Bytes {
+        let mut file: Vec<u8> = Vec::new();
+        write_parquet_report(report, 0, &mut file, options).unwrap();
+
+        Bytes::from(file)
+    }
+
+    fn key_value_metadata(file: Bytes) -> HashMap<String, String> {
+        let reader = SerializedFileReader::new(file).unwrap();
+
+        reader
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .unwrap()
+            .iter()
+            .map(|key_value| (key_value.key.clone(), key_value.value.clone().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_through_arrow() {
+        let response = read_response("metric_types.json");
+        let report = &response.reports[0];
+
+        let file = write_report(report, &Default::default());
+
+        let read_back: Vec<RecordBatch> = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read_back, vec![report_to_record_batch(report, 0).unwrap()]);
+    }
+
+    #[test]
+    fn compression_and_row_groups() {
+        let response = read_response("large_report.json");
+        let report = &response.reports[0];
+        assert!(report.data.rows.len() > 10);
+
+        let file = write_report(
+            report,
+            &ParquetOptions {
+                compression: Codec::Zstd,
+                max_row_group_size: 10,
+            },
+        );
+
+        let reader = SerializedFileReader::new(file).unwrap();
+        let metadata = reader.metadata();
+        assert_eq!(
+            metadata.num_row_groups(),
+            report.data.rows.len().div_ceil(10)
+        );
+        assert_eq!(
+            metadata.row_group(0).column(0).compression(),
+            Codec::Zstd.compression()
+        );
+    }
+
+    #[test]
+    fn rejects_empty_row_groups() {
+        let response = read_response("single_dimension_and_metric.json");
+        let options = ParquetOptions {
+            max_row_group_size: 0,
+            ..Default::default()
+        };
+        let mut file: Vec<u8> = Vec::new();
+
+        match write_parquet_report(&response.reports[0], 0, &mut file, &options) {
+            Err(err @ FlattenError::UnsupportedFeature(_)) => assert_eq!(
+                err.to_string(),
+                "unsupported: a max_row_group_size of 0, row groups need at least one row"
+            ),
+            other => panic!("expected an unsupported feature, got {:?}", other),
+        }
+        assert!(file.is_empty());
+    }
+
+    #[test]
+    fn report_metadata() {
+        let response = read_response("sampled.json");
+
+        let key_values =
+            key_value_metadata(write_report(&response.reports[0], &Default::default()));
+
+        assert_eq!(key_values[IS_SAMPLED_KEY], "true");
+        assert_eq!(key_values[IS_DATA_GOLDEN_KEY], "false");
+        assert_eq!(key_values[ROW_COUNT_KEY], "3");
+        assert_eq!(key_values[SAMPLES_READ_COUNTS_KEY], "499630");
+        assert_eq!(key_values[SAMPLING_SPACE_SIZES_KEY], "15328013");
+    }
+
+    #[test]
+    fn leaves_out_missing_metadata() {
+        let response = read_response("no_rows.json");
+
+        let key_values =
+            key_value_metadata(write_report(&response.reports[0], &Default::default()));
+
+        assert_eq!(key_values[IS_SAMPLED_KEY], "false");
+        assert_eq!(key_values[IS_DATA_GOLDEN_KEY], "true");
+        assert!(!key_values.contains_key(ROW_COUNT_KEY));
+        assert!(!key_values.contains_key(SAMPLES_READ_COUNTS_KEY));
+        assert!(!key_values.contains_key(SAMPLING_SPACE_SIZES_KEY));
+    }
+}
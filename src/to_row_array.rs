@@ -1,72 +1,491 @@
+use crate::comparison::Comparison;
+use crate::error::{FlattenError, Location};
+use crate::flat_table::{Cell, Column, FlatTable, FlatTableOptions, RowFlattener};
+use crate::streaming::{stream_report_response, RowHandler};
 use crate::types::*;
-use serde_json::value::{Number, Value};
+use serde_json::value::Value;
 use serde_json::Map;
-use std::slice::Iter;
+use std::io::{BufWriter, Read, Write};
 use std::str::FromStr;
 
-pub fn response_to_row_array(response: &ReportResponse) -> Value {
+/// How `TIME` metrics, which the API reports as a number of seconds, are emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeFormat {
+    /// A JSON number of seconds, e.g. `108.1733`
+    #[default]
+    Seconds,
+    /// An ISO 8601 duration string, e.g. `"PT1M48.1733S"`
+    Iso8601Duration,
+}
+
+/// With any of the `include_*` options turned on, each report becomes an object like
+/// `{"rows": [...], "totals": {...}, "metadata": {...}}` instead of a bare array of rows.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FlatJsonOptions {
+    pub time_format: TimeFormat,
+    pub include_totals: bool,
+    pub include_minimums: bool,
+    pub include_maximums: bool,
+    /// Include the report's sampling and paging details, see `ReportMetadata`
+    pub include_metadata: bool,
+    /// In the long layout, summaries are arrays with an object for each date range
+    pub layout: Layout,
+    /// Names for each report's date ranges, see `DelimitedOptions::date_range_labels`
+    pub date_range_labels: Vec<Vec<String>>,
+    /// Add comparisons between date ranges to each row and summary, see `Comparison`.
+    /// Percent changes from zero are `null`. Only available in the wide layout.
+    pub comparison: Option<Comparison>,
+    /// Add a `report_index` field to each row object with the index of its report, which
+    /// tells the reports apart in JSON Lines output. A report with a column of that name is
+    /// an `UnsupportedFeature` error
+    pub include_report_index: bool,
+}
+
+/// The name of the field holding the report index, see `FlatJsonOptions::include_report_index`.
+pub const REPORT_INDEX_FIELD: &str = "report_index";
+
+impl FlatJsonOptions {
+    fn includes_summaries(&self) -> bool {
+        self.include_totals || self.include_minimums || self.include_maximums
+    }
+
+    fn wraps_rows(&self) -> bool {
+        self.includes_summaries() || self.include_metadata
+    }
+}
+
+pub fn response_to_row_array(
+    response: &ReportResponse,
+    options: &FlatJsonOptions,
+) -> Result<Value, FlattenError> {
+    let table_options = options.table_options();
+    table_options.check()?;
+
     response
         .reports
         .iter()
-        .map(|report| report_to_row_array(&report))
+        .enumerate()
+        .map(|(report_index, report)| {
+            if options.includes_summaries() {
+                report.check_summaries(report_index)?;
+            }
+            let table = FlatTable::from_report(report, report_index, &table_options)?;
+            table_to_row_array(&table, report_index, options)
+        })
         .collect()
 }
 
-fn report_to_row_array(report: &Report) -> Value {
-    let report_rows = &report.data.rows;
-    if report_rows.is_empty() {
-        return json!([]);
+/// Writes every row of every report in the response to `writer` as a JSON object on its own
+/// line, as soon as it has been flattened. Summaries and metadata aren't written, whatever
+/// the `include_*` options.
+pub fn write_json_lines_reports<W: Write>(
+    response: &ReportResponse,
+    writer: &mut W,
+    options: &FlatJsonOptions,
+) -> Result<(), FlattenError> {
+    let table_options = options.table_options();
+    table_options.check()?;
+
+    let mut writer = BufWriter::new(writer);
+    for (report_index, report) in response.reports.iter().enumerate() {
+        let data = &report.data;
+        // rows have values for each date range, and so do the totals of reports without rows
+        let number_of_date_ranges = data
+            .rows
+            .first()
+            .map_or(data.totals.len(), |row| row.metrics.len());
+        let flattener = json_lines_flattener(
+            &report.column_header,
+            &report.source,
+            report_index,
+            number_of_date_ranges,
+            options,
+            &table_options,
+        )?;
+        for (row_index, row) in data.rows.iter().enumerate() {
+            write_json_line(
+                &mut writer,
+                &flattener,
+                row,
+                report_index,
+                row_index,
+                options,
+            )?;
+        }
     }
+    writer.flush()?;
 
-    let dimension_headers = &report.column_header.dimensions;
-    let metric_headers = report.get_metric_headers();
+    Ok(())
+}
 
-    let result = report_rows
-        .iter()
-        .map(|row| {
-            let mut current: Map<String, Value> = Map::new();
+/// Like `write_json_lines_reports`, but reads the response incrementally from `reader`, so
+/// memory use doesn't grow with the number of rows.
+pub fn write_json_lines_reports_from_reader<R: Read, W: Write>(
+    reader: R,
+    writer: &mut W,
+    options: &FlatJsonOptions,
+) -> Result<(), FlattenError> {
+    let table_options = options.table_options();
+    table_options.check()?;
 
-            insert_dimension_data(&mut current, row, dimension_headers);
-            insert_metric_data(&mut current, row, &metric_headers.iter());
+    let mut handler = JsonLinesRowHandler {
+        writer: BufWriter::new(writer),
+        options,
+        table_options,
+        current_report: None,
+    };
+    stream_report_response(reader, &mut handler)?;
+    handler.writer.flush()?;
 
-            Value::Object(current)
-        })
-        .collect();
+    Ok(())
+}
 
-    Value::Array(result)
+// a row flattener for a report whose rows are written as JSON Lines
+fn json_lines_flattener(
+    column_header: &ColumnHeader,
+    source: &ReportSource,
+    report_index: usize,
+    number_of_date_ranges: usize,
+    options: &FlatJsonOptions,
+    table_options: &FlatTableOptions,
+) -> Result<RowFlattener, FlattenError> {
+    let flattener = RowFlattener::new(
+        column_header,
+        source,
+        report_index,
+        number_of_date_ranges,
+        table_options,
+    )?;
+    check_report_index_field(flattener.columns(), report_index, options)?;
+
+    Ok(flattener)
 }
 
-fn insert_dimension_data(
-    current: &mut Map<String, Value>,
+fn write_json_line<W: Write>(
+    writer: &mut W,
+    flattener: &RowFlattener,
     row: &ReportRow,
-    dimension_headers: &[String],
-) {
-    for (i, header) in dimension_headers.iter().enumerate() {
-        current.insert(
-            header.to_string(),
-            Value::String(row.dimensions[i].to_string()),
+    report_index: usize,
+    row_index: usize,
+    options: &FlatJsonOptions,
+) -> Result<(), FlattenError> {
+    flattener.flatten_row(row, row_index, |cells| {
+        let row_object = row_object(
+            flattener.columns(),
+            &cells,
+            report_index,
+            row_index,
+            options,
+        )?;
+        serde_json::to_writer(&mut *writer, &row_object)?;
+        writer.write_all(b"\n")?;
+
+        Ok(())
+    })
+}
+
+struct JsonLinesRowHandler<'o, W> {
+    writer: W,
+    options: &'o FlatJsonOptions,
+    table_options: FlatTableOptions,
+    current_report: Option<StreamedReport>,
+}
+
+struct StreamedReport {
+    column_header: ColumnHeader,
+    // the number of date ranges, and so the columns, aren't known until
+    // the first row has been seen
+    flattener: Option<RowFlattener>,
+    rows_written: usize,
+}
+
+impl<'o, W: Write> RowHandler for JsonLinesRowHandler<'o, W> {
+    fn start_report(
+        &mut self,
+        _report_index: usize,
+        column_header: &ColumnHeader,
+    ) -> Result<(), FlattenError> {
+        self.current_report = Some(StreamedReport {
+            column_header: column_header.clone(),
+            flattener: None,
+            rows_written: 0,
+        });
+
+        Ok(())
+    }
+
+    fn row(&mut self, report_index: usize, row: ReportRow) -> Result<(), FlattenError> {
+        let streamed = self
+            .current_report
+            .as_mut()
+            .expect("rows are only handled after their report has started");
+
+        if streamed.flattener.is_none() {
+            streamed.flattener = Some(json_lines_flattener(
+                &streamed.column_header,
+                &ReportSource::default(),
+                report_index,
+                row.metrics.len(),
+                self.options,
+                &self.table_options,
+            )?);
+        }
+        let flattener = streamed.flattener.as_ref().unwrap();
+        write_json_line(
+            &mut self.writer,
+            flattener,
+            &row,
+            report_index,
+            streamed.rows_written,
+            self.options,
+        )?;
+        streamed.rows_written += 1;
+
+        Ok(())
+    }
+
+    // summaries and metadata aren't written as JSON Lines
+    fn end_report(&mut self, _report_index: usize, _report: &Report) -> Result<(), FlattenError> {
+        self.current_report = None;
+
+        Ok(())
+    }
+}
+
+impl FlatJsonOptions {
+    fn table_options(&self) -> FlatTableOptions {
+        FlatTableOptions {
+            layout: self.layout,
+            date_range_labels: self.date_range_labels.clone(),
+            comparison: self.comparison,
+        }
+    }
+}
+
+pub(crate) fn table_to_row_array(
+    table: &FlatTable,
+    report_index: usize,
+    options: &FlatJsonOptions,
+) -> Result<Value, FlattenError> {
+    check_report_index_field(&table.columns, report_index, options)?;
+    let rows = table
+        .rows
+        .iter()
+        .enumerate()
+        .map(|(table_row, cells)| {
+            row_object(
+                &table.columns,
+                cells,
+                report_index,
+                table.report_row_index(table_row),
+                options,
+            )
+            .map(Value::Object)
+        })
+        .collect::<Result<Vec<Value>, FlattenError>>()?;
+
+    if !options.wraps_rows() {
+        return Ok(Value::Array(rows));
+    }
+
+    let mut result: Map<String, Value> = Map::new();
+    result.insert("rows".to_string(), Value::Array(rows));
+
+    let summaries = [
+        (options.include_totals, "totals", &table.totals),
+        (options.include_minimums, "minimums", &table.minimums),
+        (options.include_maximums, "maximums", &table.maximums),
+    ];
+    for (include, key, summary) in summaries.iter() {
+        if let (true, Some(summary)) = (include, summary) {
+            let summary = summary_value(table, summary, report_index, options)?;
+            result.insert(key.to_string(), summary);
+        }
+    }
+
+    if options.include_metadata {
+        result.insert(
+            "metadata".to_string(),
+            serde_json::to_value(&table.metadata)?,
         );
     }
+
+    Ok(Value::Object(result))
 }
 
-fn insert_metric_data(
+// the report index would take the place of a column of the same name
+fn check_report_index_field(
+    columns: &[Column],
+    report_index: usize,
+    options: &FlatJsonOptions,
+) -> Result<(), FlattenError> {
+    if options.include_report_index
+        && columns
+            .iter()
+            .any(|column| column.name == REPORT_INDEX_FIELD)
+    {
+        return Err(FlattenError::UnsupportedFeature(format!(
+            "including the report index in report {}, which has a {} column",
+            report_index, REPORT_INDEX_FIELD
+        )));
+    }
+
+    Ok(())
+}
+
+// `row_index` is that of the report row the cells were flattened from
+fn row_object(
+    columns: &[Column],
+    cells: &[Cell],
+    report_index: usize,
+    row_index: usize,
+    options: &FlatJsonOptions,
+) -> Result<Map<String, Value>, FlattenError> {
+    let mut current: Map<String, Value> = Map::new();
+
+    if options.include_report_index {
+        current.insert(REPORT_INDEX_FIELD.to_string(), Value::from(report_index));
+    }
+    insert_cells(
+        &mut current,
+        columns.iter().zip(cells.iter()),
+        report_index,
+        row_index,
+        options,
+    )?;
+
+    Ok(current)
+}
+
+// summaries are objects of metrics without dimensions, or an array of those with an object
+// for each date range in the long layout. errors are located just past the report's rows
+fn summary_value(
+    table: &FlatTable,
+    summary: &[Vec<Cell>],
+    report_index: usize,
+    options: &FlatJsonOptions,
+) -> Result<Value, FlattenError> {
+    let summary_row_index = table.report_row_index(table.rows.len());
+    let summary_object = |cells: &Vec<Cell>| -> Result<Value, FlattenError> {
+        let mut current: Map<String, Value> = Map::new();
+        insert_cells(
+            &mut current,
+            table
+                .columns
+                .iter()
+                .zip(cells.iter())
+                .filter(|(column, _)| !column.kind.is_dimension()),
+            report_index,
+            summary_row_index,
+            options,
+        )?;
+
+        Ok(Value::Object(current))
+    };
+
+    match table.layout {
+        Layout::Wide => summary_object(&summary[0]),
+        Layout::Long => summary.iter().map(summary_object).collect(),
+    }
+}
+
+fn insert_cells<'t>(
     current: &mut Map<String, Value>,
-    row: &ReportRow,
-    metric_header_iter: &Iter<MetricHeaderEntry>,
-) {
-    let value_iterator = row.flat_value_iterator();
-
-    for (header, value) in metric_header_iter.clone().zip(value_iterator) {
-        current.insert(
-            header.name.clone(),
-            Value::Number(Number::from_str(value).unwrap()),
-        );
+    columns_and_cells: impl Iterator<Item = (&'t Column, &'t Cell)>,
+    report_index: usize,
+    row_index: usize,
+    options: &FlatJsonOptions,
+) -> Result<(), FlattenError> {
+    for (column, cell) in columns_and_cells {
+        let value = cell_value(column, cell, options)
+            .ok_or_else(|| invalid_metric_value(report_index, row_index, column, cell))?;
+        current.insert(column.name.clone(), value);
     }
+
+    Ok(())
+}
+
+fn cell_value(column: &Column, cell: &Cell, options: &FlatJsonOptions) -> Option<Value> {
+    match cell {
+        Cell::Text(text) => Some(Value::String(text.clone())),
+        Cell::Metric { text, number } => match (&column.metric_type, options.time_format) {
+            (Some(MetricType::Time), TimeFormat::Iso8601Duration) => {
+                iso8601_duration(text).map(Value::String)
+            }
+            _ => Some(Value::Number(number.clone())),
+        },
+        Cell::Number(number) => Some(Value::Number(number.clone())),
+        Cell::Empty => Some(Value::Null),
+    }
+}
+
+fn invalid_metric_value(
+    report_index: usize,
+    row_index: usize,
+    column: &Column,
+    cell: &Cell,
+) -> FlattenError {
+    let value = match cell {
+        Cell::Text(text) | Cell::Metric { text, .. } => text.clone(),
+        Cell::Number(number) => number.to_string(),
+        Cell::Empty => String::new(),
+    };
+
+    FlattenError::InvalidMetricValue {
+        location: Location {
+            report: report_index,
+            row: row_index,
+            column: Some(column.name.clone()),
+        },
+        value,
+    }
+}
+
+// split on the decimal point rather than going through f64 so that the
+// fractional seconds are carried over exactly as the API reported them
+fn iso8601_duration(seconds: &str) -> Option<String> {
+    let (whole, fraction) = match seconds.find('.') {
+        Some(index) => (
+            &seconds[..index],
+            seconds[index + 1..].trim_end_matches('0'),
+        ),
+        None => (seconds, ""),
+    };
+    if !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let whole_seconds = if whole.is_empty() {
+        0
+    } else {
+        u64::from_str(whole).ok()?
+    };
+
+    let hours = whole_seconds / 3600;
+    let minutes = whole_seconds % 3600 / 60;
+    let remaining_seconds = whole_seconds % 60;
+
+    let mut result = "PT".to_string();
+    if hours > 0 {
+        result.push_str(format!("{}H", hours).as_str());
+    }
+    if minutes > 0 {
+        result.push_str(format!("{}M", minutes).as_str());
+    }
+    if remaining_seconds > 0 || !fraction.is_empty() || (hours == 0 && minutes == 0) {
+        result.push_str(remaining_seconds.to_string().as_str());
+        if !fraction.is_empty() {
+            result.push('.');
+            result.push_str(fraction);
+        }
+        result.push('S');
+    }
+
+    Some(result)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::response_to_row_array;
+    use super::*;
     use crate::types::ReportResponse;
     use serde_json;
     use std::fs;
@@ -81,7 +500,10 @@ mod tests {
 
         let parsed_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
 
-        assert_eq!(response_to_row_array(&parsed_response), json!([[]]))
+        assert_eq!(
+            response_to_row_array(&parsed_response, &FlatJsonOptions::default()).unwrap(),
+            json!([[]])
+        )
     }
 
     #[test]
@@ -94,7 +516,7 @@ mod tests {
         let parsed_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
 
         assert_eq!(
-            response_to_row_array(&parsed_response),
+            response_to_row_array(&parsed_response, &FlatJsonOptions::default()).unwrap(),
             json!([[{"ga:sessions": 44}]])
         )
     }
@@ -110,7 +532,7 @@ mod tests {
         let parsed_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
 
         assert_eq!(
-            response_to_row_array(&parsed_response),
+            response_to_row_array(&parsed_response, &FlatJsonOptions::default()).unwrap(),
             json!([
                 [{
                     "ga:deviceCategory": "desktop",
@@ -135,7 +557,7 @@ mod tests {
         let parsed_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
 
         assert_eq!(
-            response_to_row_array(&parsed_response),
+            response_to_row_array(&parsed_response, &FlatJsonOptions::default()).unwrap(),
             json!([
                 [{
                     "ga:deviceCategory": "desktop",
@@ -171,7 +593,12 @@ mod tests {
 
         let parsed_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
 
-        assert!(response_to_row_array(&parsed_response).as_array().is_some())
+        assert!(
+            response_to_row_array(&parsed_response, &FlatJsonOptions::default())
+                .unwrap()
+                .as_array()
+                .is_some()
+        )
     }
 
     #[test]
@@ -184,7 +611,7 @@ mod tests {
         let deserialized_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
 
         assert_eq!(
-            response_to_row_array(&deserialized_response),
+            response_to_row_array(&deserialized_response, &FlatJsonOptions::default()).unwrap(),
             json!([
                 [{
                   "ga:deviceCategory": "desktop",
@@ -230,7 +657,7 @@ mod tests {
         let parsed_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
 
         assert_eq!(
-            response_to_row_array(&parsed_response),
+            response_to_row_array(&parsed_response, &FlatJsonOptions::default()).unwrap(),
             json!([
                 [{
                     "ga:browser": "Chrome",
@@ -265,7 +692,7 @@ mod tests {
         let parsed_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
 
         assert_eq!(
-            response_to_row_array(&parsed_response),
+            response_to_row_array(&parsed_response, &FlatJsonOptions::default()).unwrap(),
             json!([
                 [{
                     "ga:browser": "Android Webview",
@@ -300,4 +727,368 @@ mod tests {
             ])
         )
     }
+
+    #[test]
+    fn pivot() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/pivot.json"),
+        )
+        .unwrap();
+
+        let parsed_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        assert_eq!(
+            response_to_row_array(&parsed_response, &FlatJsonOptions::default()).unwrap(),
+            json!([
+                [{
+                    "ga:deviceCategory": "desktop",
+                    "ga:users": 38,
+                    "pivot1_201831_ga:sessions": 43,
+                    "pivot1_201832_ga:sessions": 12,
+                },
+                {
+                    "ga:deviceCategory": "mobile",
+                    "ga:users": 1,
+                    "pivot1_201831_ga:sessions": 1,
+                    "pivot1_201832_ga:sessions": 0,
+                }]
+            ])
+        )
+    }
+
+    #[test]
+    fn integer_metrics_stay_integers_and_float_metrics_become_floats() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/metric_types.json"),
+        )
+        .unwrap();
+
+        let parsed_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        assert_eq!(
+            response_to_row_array(&parsed_response, &FlatJsonOptions::default()).unwrap(),
+            json!([
+                [{
+                    "ga:country": "Canada",
+                    "ga:sessions": 12,
+                    "ga:bounceRate": 50.0,
+                    "ga:transactionRevenue": 10.0,
+                    "ga:pageviewsPerSession": 2.5,
+                    "ga:avgSessionDuration": 3725.5,
+                }]
+            ])
+        )
+    }
+
+    #[test]
+    fn time_metrics_as_iso8601_durations() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/multiple_date_ranges.json"),
+        )
+        .unwrap();
+
+        let parsed_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        let options = FlatJsonOptions {
+            time_format: TimeFormat::Iso8601Duration,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            response_to_row_array(&parsed_response, &options).unwrap(),
+            json!([
+                [{
+                    "ga:browser": "Chrome",
+                    "ga:avgTimeOnPage": "PT1M48.1733S",
+                    "ga:pageviewsPerSession": 2.93126,
+                    "ga:avgTimeOnPage_2": "PT2M9.7071651S",
+                    "ga:pageviewsPerSession_2": 3.60975609,
+                }, {
+                    "ga:browser": "Edge",
+                    "ga:avgTimeOnPage": "PT51.794117S",
+                    "ga:pageviewsPerSession": 6.6666667,
+                    "ga:avgTimeOnPage_2": "PT3M30.866667S",
+                    "ga:pageviewsPerSession_2": 2.875,
+                }, {
+                    "ga:browser": "Firefox",
+                    "ga:avgTimeOnPage": "PT2M3.657142S",
+                    "ga:pageviewsPerSession": 2.09375,
+                    "ga:avgTimeOnPage_2": "PT1M15.333333S",
+                    "ga:pageviewsPerSession_2": 1.5,
+                }]
+            ])
+        )
+    }
+
+    #[test]
+    fn iso8601_duration_formatting() {
+        assert_eq!(iso8601_duration("0"), Some("PT0S".to_string()));
+        assert_eq!(iso8601_duration("0.0"), Some("PT0S".to_string()));
+        assert_eq!(iso8601_duration("60"), Some("PT1M".to_string()));
+        assert_eq!(iso8601_duration("3725.50"), Some("PT1H2M5.5S".to_string()));
+        assert_eq!(iso8601_duration("7200"), Some("PT2H".to_string()));
+        assert_eq!(iso8601_duration("-5"), None);
+        assert_eq!(iso8601_duration("1.5e3"), None);
+    }
+
+    #[test]
+    fn includes_summaries_across_date_ranges() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/multiple_date_ranges.json"),
+        )
+        .unwrap();
+
+        let parsed_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        let options = FlatJsonOptions {
+            include_totals: true,
+            include_maximums: true,
+            ..Default::default()
+        };
+
+        let result = response_to_row_array(&parsed_response, &options).unwrap();
+
+        assert_eq!(result[0]["rows"].as_array().unwrap().len(), 3);
+        assert_eq!(
+            result[0]["totals"],
+            json!({
+                "ga:avgTimeOnPage": 89.31735436893204,
+                "ga:pageviewsPerSession": 3.4342688330871494,
+                "ga:avgTimeOnPage_2": 155.70253164556962,
+                "ga:pageviewsPerSession_2": 3.0343347639484977,
+            })
+        );
+        assert_eq!(
+            result[0]["maximums"],
+            json!({
+                "ga:avgTimeOnPage": 364.8,
+                "ga:pageviewsPerSession": 6.666666666666667,
+                "ga:avgTimeOnPage_2": 267.4,
+                "ga:pageviewsPerSession_2": 5.4,
+            })
+        );
+        assert!(result[0].get("minimums").is_none());
+    }
+
+    #[test]
+    fn long_layout() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/multiple_date_ranges.json"),
+        )
+        .unwrap();
+
+        let parsed_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        let options = FlatJsonOptions {
+            layout: Layout::Long,
+            include_totals: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            response_to_row_array(&parsed_response, &options).unwrap(),
+            json!([{
+                "rows": [
+                    {"ga:browser": "Chrome", "date_range": 1, "ga:avgTimeOnPage": 108.1733, "ga:pageviewsPerSession": 2.93126},
+                    {"ga:browser": "Chrome", "date_range": 2, "ga:avgTimeOnPage": 129.7071651, "ga:pageviewsPerSession": 3.60975609},
+                    {"ga:browser": "Edge", "date_range": 1, "ga:avgTimeOnPage": 51.794117, "ga:pageviewsPerSession": 6.6666667},
+                    {"ga:browser": "Edge", "date_range": 2, "ga:avgTimeOnPage": 210.866667, "ga:pageviewsPerSession": 2.875},
+                    {"ga:browser": "Firefox", "date_range": 1, "ga:avgTimeOnPage": 123.657142, "ga:pageviewsPerSession": 2.09375},
+                    {"ga:browser": "Firefox", "date_range": 2, "ga:avgTimeOnPage": 75.333333, "ga:pageviewsPerSession": 1.5},
+                ],
+                "totals": [
+                    {"date_range": 1, "ga:avgTimeOnPage": 89.31735436893204, "ga:pageviewsPerSession": 3.4342688330871494},
+                    {"date_range": 2, "ga:avgTimeOnPage": 155.70253164556962, "ga:pageviewsPerSession": 3.0343347639484977},
+                ],
+            }])
+        )
+    }
+
+    #[test]
+    fn long_layout_with_labelled_date_ranges() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/multiple_date_ranges.json"),
+        )
+        .unwrap();
+
+        let parsed_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        let options = FlatJsonOptions {
+            layout: Layout::Long,
+            date_range_labels: vec![vec!["current".to_string(), "previous".to_string()]],
+            ..Default::default()
+        };
+
+        let result = response_to_row_array(&parsed_response, &options).unwrap();
+
+        assert_eq!(
+            result[0][1],
+            json!({
+                "ga:browser": "Chrome",
+                "date_range": "previous",
+                "ga:avgTimeOnPage": 129.7071651,
+                "ga:pageviewsPerSession": 3.60975609,
+            })
+        );
+    }
+
+    #[test]
+    fn compares_date_ranges() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/multiple_date_ranges.json"),
+        )
+        .unwrap();
+
+        let parsed_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        let options = FlatJsonOptions {
+            comparison: Some(Comparison { base_date_range: 1 }),
+            date_range_labels: vec![vec!["current".to_string(), "previous".to_string()]],
+            ..Default::default()
+        };
+
+        let result = response_to_row_array(&parsed_response, &options).unwrap();
+
+        assert_eq!(
+            result[0][2],
+            json!({
+                "ga:browser": "Firefox",
+                "ga:avgTimeOnPage[current]": 123.657142,
+                "ga:pageviewsPerSession[current]": 2.09375,
+                "ga:avgTimeOnPage[previous]": 75.333333,
+                "ga:pageviewsPerSession[previous]": 1.5,
+                "ga:avgTimeOnPage[current]_delta": 48.323809,
+                "ga:avgTimeOnPage[current]_pct_change": 64.14664939887898,
+                "ga:pageviewsPerSession[current]_delta": 0.59375,
+                "ga:pageviewsPerSession[current]_pct_change": 39.58333333333333,
+            })
+        );
+    }
+
+    #[test]
+    fn json_lines() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/multiple_reports.json"),
+        )
+        .unwrap();
+
+        let parsed_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        let options = FlatJsonOptions {
+            include_report_index: true,
+            include_totals: true,
+            ..Default::default()
+        };
+
+        let mut output: Vec<u8> = Vec::new();
+        write_json_lines_reports(&parsed_response, &mut output, &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert!(output.ends_with("}\n"));
+        assert_eq!(lines.len(), 7);
+        assert_eq!(
+            lines[0],
+            json!({"report_index": 0, "ga:deviceCategory": "desktop", "ga:sessions": 25, "ga:bounces": 17})
+        );
+        assert_eq!(
+            lines[2],
+            json!({"report_index": 1, "ga:country": "Azerbaijan", "ga:sessions": 1, "ga:bounces": 0})
+        );
+
+        let mut streamed: Vec<u8> = Vec::new();
+        write_json_lines_reports_from_reader(data.as_bytes(), &mut streamed, &options).unwrap();
+        assert_eq!(String::from_utf8(streamed).unwrap(), output);
+    }
+
+    #[test]
+    fn rejects_a_column_named_like_the_report_index() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/multiple_reports.json"),
+        )
+        .unwrap();
+
+        let mut parsed_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+        parsed_response.reports[1].column_header.dimensions[0] = REPORT_INDEX_FIELD.to_string();
+        let options = FlatJsonOptions {
+            include_report_index: true,
+            ..Default::default()
+        };
+
+        let mut output: Vec<u8> = Vec::new();
+        match write_json_lines_reports(&parsed_response, &mut output, &options) {
+            Err(FlattenError::UnsupportedFeature(message)) => assert_eq!(
+                message,
+                "including the report index in report 1, which has a report_index column"
+            ),
+            other => panic!("expected an unsupported feature, got {:?}", other),
+        }
+        assert!(response_to_row_array(&parsed_response, &options).is_err());
+
+        // without the report index, the column is written like any other
+        let lines = response_to_row_array(&parsed_response, &FlatJsonOptions::default()).unwrap();
+        assert_eq!(lines[1][0]["report_index"], json!("Azerbaijan"));
+    }
+
+    #[test]
+    fn includes_totals_without_rows() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/no_rows.json"),
+        )
+        .unwrap();
+
+        let parsed_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        let options = FlatJsonOptions {
+            include_totals: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            response_to_row_array(&parsed_response, &options).unwrap(),
+            json!([{
+                "rows": [],
+                "totals": {"ga:sessions": 0},
+            }])
+        )
+    }
+
+    #[test]
+    fn includes_metadata() {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/sampled.json"),
+        )
+        .unwrap();
+
+        let parsed_response: ReportResponse = serde_json::from_str(data.as_str()).unwrap();
+
+        let options = FlatJsonOptions {
+            include_metadata: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            response_to_row_array(&parsed_response, &options).unwrap(),
+            json!([{
+                "rows": [{
+                    "ga:deviceCategory": "desktop",
+                    "ga:sessions": 43,
+                }, {
+                    "ga:deviceCategory": "mobile",
+                    "ga:sessions": 1,
+                }],
+                "metadata": {
+                    "rowCount": 3,
+                    "isDataGolden": false,
+                    "isSampled": true,
+                    "samplesReadCounts": ["499630"],
+                    "samplingSpaceSizes": ["15328013"],
+                    "nextPageToken": "2",
+                },
+            }])
+        )
+    }
 }
@@ -1,3 +1,6 @@
+use crate::error::{FlattenError, Location};
+use itertools::Itertools;
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct ReportResponse {
@@ -10,6 +13,24 @@ pub struct Report {
     pub column_header: ColumnHeader,
     pub data: ReportData,
     pub next_page_token: Option<String>,
+    /// Set when the report was read from an API other than v4, see `ReportSource`
+    #[serde(skip)]
+    pub source: ReportSource,
+}
+
+/// How a report read from another API differs from a v4 one, for the flatteners to take
+/// into account. Reports read as v4 have the default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReportSource {
+    /// The source had a total, minimum and maximum for each date range, which a v4 report
+    /// can't hold, so asking for summaries is an error rather than leaving them out
+    pub summaries_by_date_range: bool,
+    /// The source leaves out metric values it has no data for, as GA4 pivots do for missing
+    /// combinations, so empty metrics flatten to empty cells instead of being invalid
+    pub empty_metrics_are_missing: bool,
+    /// The index of the dimension numbering the steps of exploded conversion paths, which
+    /// flattens to a `ColumnKind::Step` column of numbers
+    pub step_dimension: Option<usize>,
 }
 
 impl Report {
@@ -17,24 +38,33 @@ impl Report {
         self.data.rows.is_empty()
     }
 
-    // we want sets of headers like x, y, x_2, y_2, ..., x_n, y_n
-    // to match the shape of the data
     pub fn get_metric_headers(&self) -> Vec<MetricHeaderEntry> {
-        let base_items = &self.column_header.metric_header.metric_header_entries;
+        self.column_header
+            .get_metric_headers(self.number_of_date_ranges())
+    }
 
-        let mut result = base_items.clone();
+    pub fn metadata(&self) -> ReportMetadata {
+        let data = &self.data;
 
-        for date_range_num in 1..self.number_of_date_ranges() {
-            for entry in base_items.iter() {
-                let temp_entry = MetricHeaderEntry {
-                    name: format!("{}_{}", &entry.name, date_range_num + 1),
-                    metric_type: (*entry).metric_type.clone(),
-                };
-                result.push(temp_entry);
-            }
+        ReportMetadata {
+            row_count: data.row_count,
+            is_data_golden: data.is_data_golden,
+            is_sampled: data.samples_read_counts.is_some() || data.sampling_space_sizes.is_some(),
+            samples_read_counts: data.samples_read_counts.clone(),
+            sampling_space_sizes: data.sampling_space_sizes.clone(),
+            next_page_token: self.next_page_token.clone(),
+        }
+    }
+
+    pub(crate) fn check_summaries(&self, report_index: usize) -> Result<(), FlattenError> {
+        if self.source.summaries_by_date_range {
+            return Err(FlattenError::UnsupportedFeature(format!(
+                "summaries of report {}, which has one for each of its date ranges",
+                report_index
+            )));
         }
 
-        result
+        Ok(())
     }
 
     fn number_of_date_ranges(&self) -> usize {
@@ -46,7 +76,61 @@ impl Report {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// How reports with multiple date ranges are flattened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// One row per set of dimension values, with a set of metric columns for each date
+    /// range: `ga:sessions`, `ga:sessions_2`, ...
+    #[default]
+    Wide,
+    /// One row per set of dimension values and date range, with a `date_range` column
+    /// numbering the date ranges from 1 and a single set of metric columns
+    Long,
+}
+
+/// The name of the column holding the date range number, or label, in the long layout.
+pub const DATE_RANGE_COLUMN: &str = "date_range";
+
+/// The labels for a report's date ranges, if any were given for it.
+///
+/// `number_of_date_ranges` is the number of date ranges in the report's values, or 0 when
+/// that isn't known (a report without rows), in which case the labels aren't checked.
+pub fn report_date_range_labels(
+    date_range_labels: &[Vec<String>],
+    report_index: usize,
+    number_of_date_ranges: usize,
+) -> Result<Option<&[String]>, FlattenError> {
+    match date_range_labels.get(report_index) {
+        Some(labels) if !labels.is_empty() => {
+            if number_of_date_ranges > 0 && labels.len() != number_of_date_ranges {
+                return Err(FlattenError::DateRangeMismatch {
+                    report: report_index,
+                    expected: number_of_date_ranges,
+                    found: labels.len(),
+                });
+            }
+            Ok(Some(labels.as_slice()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Everything about a report besides its headers and values.
+///
+/// The API only includes sampling counts when the data was sampled, so `is_sampled` is
+/// set whenever they are present.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportMetadata {
+    pub row_count: Option<u32>,
+    pub is_data_golden: Option<bool>,
+    pub is_sampled: bool,
+    pub samples_read_counts: Option<Vec<String>>,
+    pub sampling_space_sizes: Option<Vec<String>>,
+    pub next_page_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct ColumnHeader {
     #[serde(default)]
@@ -54,21 +138,134 @@ pub struct ColumnHeader {
     pub metric_header: MetricHeader,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl ColumnHeader {
+    // in the long layout every row has a single date range's worth of metrics,
+    // otherwise labels replace the numbered suffixes
+    pub fn get_layout_metric_headers(
+        &self,
+        layout: Layout,
+        number_of_date_ranges: usize,
+        date_range_labels: Option<&[String]>,
+    ) -> Vec<MetricHeaderEntry> {
+        match (layout, date_range_labels) {
+            (Layout::Wide, None) => self.get_metric_headers(number_of_date_ranges),
+            (Layout::Wide, Some(labels)) => self.get_labelled_metric_headers(labels),
+            (Layout::Long, _) => self.get_metric_headers(1),
+        }
+    }
+
+    // headers like x[current], y[current], x[previous], y[previous]
+    pub fn get_labelled_metric_headers(
+        &self,
+        date_range_labels: &[String],
+    ) -> Vec<MetricHeaderEntry> {
+        let base_items = self.metric_header.flat_header_entries();
+
+        date_range_labels
+            .iter()
+            .flat_map(|label| {
+                base_items.iter().map(move |entry| MetricHeaderEntry {
+                    name: format!("{}[{}]", &entry.name, label),
+                    metric_type: entry.metric_type.clone(),
+                })
+            })
+            .collect()
+    }
+
+    // we want sets of headers like x, y, x_2, y_2, ..., x_n, y_n
+    // to match the shape of the data
+    pub fn get_metric_headers(&self, number_of_date_ranges: usize) -> Vec<MetricHeaderEntry> {
+        let base_items = self.metric_header.flat_header_entries();
+
+        let mut result = base_items.clone();
+
+        for date_range_num in 1..number_of_date_ranges {
+            for entry in base_items.iter() {
+                let temp_entry = MetricHeaderEntry {
+                    name: format!("{}_{}", &entry.name, date_range_num + 1),
+                    metric_type: entry.metric_type.clone(),
+                };
+                result.push(temp_entry);
+            }
+        }
+
+        result
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct MetricHeader {
+    #[serde(default)]
     pub metric_header_entries: Vec<MetricHeaderEntry>,
-    // pivotHeaders: Vec<PivotHeader>
+    #[serde(default)]
+    pub pivot_headers: Vec<PivotHeader>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl MetricHeader {
+    // the headers for a single date range: the regular metrics followed by
+    // one entry per pivot column, in the same order as DateRangeValue values.
+    // pivot columns are prefixed with their pivot's number, so two pivots
+    // sharing dimension values and a metric still get distinct columns
+    pub fn flat_header_entries(&self) -> Vec<MetricHeaderEntry> {
+        let mut entries = self.metric_header_entries.clone();
+
+        for (pivot_index, pivot_header) in self.pivot_headers.iter().enumerate() {
+            for pivot_entry in pivot_header.pivot_header_entries.iter() {
+                let mut entry = pivot_entry.to_metric_header_entry();
+                entry.name = format!("pivot{}_{}", pivot_index + 1, entry.name);
+                entries.push(entry);
+            }
+        }
+
+        entries
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PivotHeader {
+    #[serde(default)]
+    pub pivot_header_entries: Vec<PivotHeaderEntry>,
+    pub total_pivot_groups_count: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PivotHeaderEntry {
+    #[serde(default)]
+    pub dimension_names: Vec<String>,
+    #[serde(default)]
+    pub dimension_values: Vec<String>,
+    pub metric: MetricHeaderEntry,
+}
+
+impl PivotHeaderEntry {
+    // pivot entries are named like 201831_ga:sessions, with one leading
+    // value per pivot dimension
+    pub fn column_name(&self) -> String {
+        self.dimension_values
+            .iter()
+            .chain(std::iter::once(&self.metric.name))
+            .join("_")
+    }
+
+    fn to_metric_header_entry(&self) -> MetricHeaderEntry {
+        MetricHeaderEntry {
+            name: self.column_name(),
+            metric_type: self.metric.metric_type.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct MetricHeaderEntry {
     pub name: String,
     #[serde(rename = "type")]
     pub metric_type: MetricType,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum MetricType {
     MetricTypeUnspecified,
@@ -101,15 +298,90 @@ pub struct ReportRow {
 }
 
 impl ReportRow {
-    pub fn flat_value_iterator<'a>(&'a self) -> impl Iterator<Item = &String> {
-        self.metrics
-            .iter()
-            .flat_map(|value: &'a DateRangeValue| value.values.iter())
+    pub fn flat_value_iterator(&self) -> impl Iterator<Item = &String> {
+        flat_date_range_values(&self.metrics)
+    }
+
+    // a row that doesn't line up with the column header would otherwise have
+    // its values attributed to the wrong columns. metric_headers are the
+    // headers for the layout, so a single date range's worth when it is Long
+    pub fn check_shape(
+        &self,
+        dimension_headers: &[String],
+        metric_headers: &[MetricHeaderEntry],
+        layout: Layout,
+        report_index: usize,
+        row_index: usize,
+    ) -> Result<(), FlattenError> {
+        let mismatch =
+            |expected: usize, found: usize, column: Option<&String>| FlattenError::ShapeMismatch {
+                location: Location {
+                    report: report_index,
+                    row: row_index,
+                    column: column.cloned(),
+                },
+                expected,
+                found,
+            };
+
+        if self.dimensions.len() != dimension_headers.len() {
+            return Err(mismatch(
+                dimension_headers.len(),
+                self.dimensions.len(),
+                dimension_headers.get(self.dimensions.len()),
+            ));
+        }
+
+        let metric_counts: Vec<usize> = match layout {
+            Layout::Wide => vec![self.flat_value_iterator().count()],
+            Layout::Long => self
+                .metrics
+                .iter()
+                .map(|value| value.flat_value_iterator().count())
+                .collect(),
+        };
+        for metric_count in metric_counts {
+            if metric_count != metric_headers.len() {
+                return Err(mismatch(
+                    metric_headers.len(),
+                    metric_count,
+                    metric_headers.get(metric_count).map(|entry| &entry.name),
+                ));
+            }
+        }
+
+        Ok(())
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct DateRangeValue {
+    #[serde(default)]
+    pub values: Vec<String>,
+    #[serde(default)]
+    pub pivot_value_regions: Vec<PivotValueRegion>,
+}
+
+// values for every date range, in the same order as Report::get_metric_headers
+pub fn flat_date_range_values(values: &[DateRangeValue]) -> impl Iterator<Item = &String> {
+    values
+        .iter()
+        .flat_map(|value: &DateRangeValue| value.flat_value_iterator())
+}
+
+impl DateRangeValue {
+    pub fn flat_value_iterator(&self) -> impl Iterator<Item = &String> {
+        self.values.iter().chain(
+            self.pivot_value_regions
+                .iter()
+                .flat_map(|region| region.values.iter()),
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PivotValueRegion {
+    #[serde(default)]
     pub values: Vec<String>,
-    // pivotValueRegions: Vec<PivotValueRegion>,
 }
@@ -0,0 +1,636 @@
+use crate::error::{FlattenError, Location};
+use crate::flat_table::{Cell, Column, ColumnKind, FlatTable};
+use crate::to_delimited::{check_options, write_table, DelimitedOptions, OutputPosition};
+use crate::to_row_array::{table_to_row_array, FlatJsonOptions};
+use crate::types::{Layout, MetricType, ReportMetadata};
+use serde_json::value::{Number, Value};
+use std::io::Write;
+use std::str::FromStr;
+
+/// The response of `userActivity:search`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UserActivityResponse {
+    #[serde(default)]
+    pub sessions: Vec<UserActivitySession>,
+    pub total_rows: Option<u32>,
+    pub next_page_token: Option<String>,
+    pub sample_rate: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UserActivitySession {
+    pub session_id: Option<String>,
+    pub device_category: Option<String>,
+    pub platform: Option<String>,
+    pub data_source: Option<String>,
+    pub session_date: Option<String>,
+    #[serde(default)]
+    pub activities: Vec<Activity>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Activity {
+    pub activity_time: Option<String>,
+    pub activity_type: Option<String>,
+    pub source: Option<String>,
+    pub medium: Option<String>,
+    pub channel_grouping: Option<String>,
+    pub campaign: Option<String>,
+    pub keyword: Option<String>,
+    pub hostname: Option<String>,
+    pub landing_page_path: Option<String>,
+    pub pageview: Option<PageviewData>,
+    pub appview: Option<ScreenviewData>,
+    pub event: Option<EventData>,
+    pub goals: Option<GoalSetData>,
+    pub ecommerce: Option<EcommerceData>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PageviewData {
+    pub page_path: Option<String>,
+    pub page_title: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ScreenviewData {
+    pub screen_name: Option<String>,
+    pub app_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EventData {
+    pub event_category: Option<String>,
+    pub event_action: Option<String>,
+    pub event_label: Option<String>,
+    pub event_value: Option<String>,
+    pub event_count: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GoalSetData {
+    #[serde(default)]
+    pub goals: Vec<GoalData>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GoalData {
+    pub goal_index: Option<u32>,
+    pub goal_name: Option<String>,
+    pub goal_completions: Option<String>,
+    pub goal_value: Option<f64>,
+    pub goal_completion_location: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EcommerceData {
+    pub action_type: Option<String>,
+    pub transaction: Option<TransactionData>,
+    #[serde(default)]
+    pub products: Vec<ProductData>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionData {
+    pub transaction_id: Option<String>,
+    pub transaction_revenue: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ProductData {
+    pub product_sku: Option<String>,
+}
+
+// a column of flattened activities, with how to get an activity's cell. the
+// integer columns fail with the field's value when it isn't an integer
+struct ActivityColumn {
+    name: &'static str,
+    metric_type: Option<MetricType>,
+    cell: fn(&UserActivitySession, &Activity) -> Result<Cell, String>,
+}
+
+fn text(value: &Option<String>) -> Cell {
+    value.clone().map_or(Cell::Empty, Cell::Text)
+}
+
+fn float(value: Option<f64>) -> Cell {
+    value
+        .and_then(Number::from_f64)
+        .map_or(Cell::Empty, |number| Cell::Metric {
+            text: number.to_string(),
+            number,
+        })
+}
+
+// int64 fields are strings in the API's JSON
+fn integer(value: &Option<String>) -> Result<Cell, String> {
+    match value {
+        Some(value) => i64::from_str(value)
+            .map(|integer| Cell::Metric {
+                text: integer.to_string(),
+                number: Number::from(integer),
+            })
+            .map_err(|_| value.clone()),
+        None => Ok(Cell::Empty),
+    }
+}
+
+fn joined<'a>(values: impl Iterator<Item = &'a Option<String>>) -> Cell {
+    let values: Vec<&str> = values.flatten().map(String::as_str).collect();
+    if values.is_empty() {
+        Cell::Empty
+    } else {
+        Cell::Text(values.join(", "))
+    }
+}
+
+fn pageview(activity: &Activity, field: fn(&PageviewData) -> &Option<String>) -> Cell {
+    activity
+        .pageview
+        .as_ref()
+        .map_or(Cell::Empty, |pageview| text(field(pageview)))
+}
+
+fn appview(activity: &Activity, field: fn(&ScreenviewData) -> &Option<String>) -> Cell {
+    activity
+        .appview
+        .as_ref()
+        .map_or(Cell::Empty, |appview| text(field(appview)))
+}
+
+fn event(activity: &Activity, field: fn(&EventData) -> &Option<String>) -> &Option<String> {
+    activity.event.as_ref().map_or(&None, field)
+}
+
+fn goals(activity: &Activity) -> &[GoalData] {
+    activity
+        .goals
+        .as_ref()
+        .map_or(&[], |goal_set| goal_set.goals.as_slice())
+}
+
+// fails with the completions that overflow the total
+fn goal_completions(activity: &Activity) -> Result<Cell, String> {
+    let goals = goals(activity);
+    if goals.is_empty() {
+        return Ok(Cell::Empty);
+    }
+
+    let mut total: i64 = 0;
+    for goal in goals {
+        if let Cell::Metric { text, number } = integer(&goal.goal_completions)? {
+            total = number
+                .as_i64()
+                .and_then(|completions| total.checked_add(completions))
+                .ok_or(text)?;
+        }
+    }
+
+    Ok(Cell::Metric {
+        text: total.to_string(),
+        number: Number::from(total),
+    })
+}
+
+fn goal_value(activity: &Activity) -> Cell {
+    let goals = goals(activity);
+    if goals.is_empty() {
+        return Cell::Empty;
+    }
+
+    float(Some(goals.iter().filter_map(|goal| goal.goal_value).sum()))
+}
+
+fn ecommerce(activity: &Activity) -> Option<&EcommerceData> {
+    activity.ecommerce.as_ref()
+}
+
+fn transaction(activity: &Activity) -> Option<&TransactionData> {
+    ecommerce(activity).and_then(|ecommerce| ecommerce.transaction.as_ref())
+}
+
+const ACTIVITY_COLUMNS: [ActivityColumn; 30] = [
+    ActivityColumn {
+        name: "sessionId",
+        metric_type: None,
+        cell: |session, _| Ok(text(&session.session_id)),
+    },
+    ActivityColumn {
+        name: "deviceCategory",
+        metric_type: None,
+        cell: |session, _| Ok(text(&session.device_category)),
+    },
+    ActivityColumn {
+        name: "platform",
+        metric_type: None,
+        cell: |session, _| Ok(text(&session.platform)),
+    },
+    ActivityColumn {
+        name: "dataSource",
+        metric_type: None,
+        cell: |session, _| Ok(text(&session.data_source)),
+    },
+    ActivityColumn {
+        name: "sessionDate",
+        metric_type: None,
+        cell: |session, _| Ok(text(&session.session_date)),
+    },
+    ActivityColumn {
+        name: "activityTime",
+        metric_type: None,
+        cell: |_, activity| Ok(text(&activity.activity_time)),
+    },
+    ActivityColumn {
+        name: "activityType",
+        metric_type: None,
+        cell: |_, activity| Ok(text(&activity.activity_type)),
+    },
+    ActivityColumn {
+        name: "source",
+        metric_type: None,
+        cell: |_, activity| Ok(text(&activity.source)),
+    },
+    ActivityColumn {
+        name: "medium",
+        metric_type: None,
+        cell: |_, activity| Ok(text(&activity.medium)),
+    },
+    ActivityColumn {
+        name: "channelGrouping",
+        metric_type: None,
+        cell: |_, activity| Ok(text(&activity.channel_grouping)),
+    },
+    ActivityColumn {
+        name: "campaign",
+        metric_type: None,
+        cell: |_, activity| Ok(text(&activity.campaign)),
+    },
+    ActivityColumn {
+        name: "keyword",
+        metric_type: None,
+        cell: |_, activity| Ok(text(&activity.keyword)),
+    },
+    ActivityColumn {
+        name: "hostname",
+        metric_type: None,
+        cell: |_, activity| Ok(text(&activity.hostname)),
+    },
+    ActivityColumn {
+        name: "landingPagePath",
+        metric_type: None,
+        cell: |_, activity| Ok(text(&activity.landing_page_path)),
+    },
+    ActivityColumn {
+        name: "pagePath",
+        metric_type: None,
+        cell: |_, activity| Ok(pageview(activity, |pageview| &pageview.page_path)),
+    },
+    ActivityColumn {
+        name: "pageTitle",
+        metric_type: None,
+        cell: |_, activity| Ok(pageview(activity, |pageview| &pageview.page_title)),
+    },
+    ActivityColumn {
+        name: "screenName",
+        metric_type: None,
+        cell: |_, activity| Ok(appview(activity, |appview| &appview.screen_name)),
+    },
+    ActivityColumn {
+        name: "appName",
+        metric_type: None,
+        cell: |_, activity| Ok(appview(activity, |appview| &appview.app_name)),
+    },
+    ActivityColumn {
+        name: "eventCategory",
+        metric_type: None,
+        cell: |_, activity| Ok(text(event(activity, |event| &event.event_category))),
+    },
+    ActivityColumn {
+        name: "eventAction",
+        metric_type: None,
+        cell: |_, activity| Ok(text(event(activity, |event| &event.event_action))),
+    },
+    ActivityColumn {
+        name: "eventLabel",
+        metric_type: None,
+        cell: |_, activity| Ok(text(event(activity, |event| &event.event_label))),
+    },
+    ActivityColumn {
+        name: "eventValue",
+        metric_type: Some(MetricType::Integer),
+        cell: |_, activity| integer(event(activity, |event| &event.event_value)),
+    },
+    ActivityColumn {
+        name: "eventCount",
+        metric_type: Some(MetricType::Integer),
+        cell: |_, activity| integer(event(activity, |event| &event.event_count)),
+    },
+    ActivityColumn {
+        name: "goalNames",
+        metric_type: None,
+        cell: |_, activity| Ok(joined(goals(activity).iter().map(|goal| &goal.goal_name))),
+    },
+    ActivityColumn {
+        name: "goalCompletions",
+        metric_type: Some(MetricType::Integer),
+        cell: |_, activity| goal_completions(activity),
+    },
+    ActivityColumn {
+        name: "goalValue",
+        metric_type: Some(MetricType::Float),
+        cell: |_, activity| Ok(goal_value(activity)),
+    },
+    ActivityColumn {
+        name: "actionType",
+        metric_type: None,
+        cell: |_, activity| {
+            Ok(ecommerce(activity).map_or(Cell::Empty, |ecommerce| text(&ecommerce.action_type)))
+        },
+    },
+    ActivityColumn {
+        name: "transactionId",
+        metric_type: None,
+        cell: |_, activity| {
+            Ok(transaction(activity)
+                .map_or(Cell::Empty, |transaction| text(&transaction.transaction_id)))
+        },
+    },
+    ActivityColumn {
+        name: "transactionRevenue",
+        metric_type: Some(MetricType::Float),
+        cell: |_, activity| {
+            Ok(float(
+                transaction(activity).and_then(|transaction| transaction.transaction_revenue),
+            ))
+        },
+    },
+    ActivityColumn {
+        name: "productSkus",
+        metric_type: None,
+        cell: |_, activity| {
+            Ok(joined(
+                ecommerce(activity)
+                    .map_or(&[][..], |ecommerce| ecommerce.products.as_slice())
+                    .iter()
+                    .map(|product| &product.product_sku),
+            ))
+        },
+    },
+];
+
+/// The columns of a flattened user activity, in order: the session's fields, the fields
+/// every activity has, then those of each activity type. Columns that don't apply to an
+/// activity are empty, or `null` in JSON.
+///
+/// An activity's goals and products are collapsed into its single row: goal names and
+/// product SKUs are joined with `", "`, and goal completions and values summed. Which goal
+/// each completion or value was for, the goals' indices and completion locations are not
+/// kept.
+pub const USER_ACTIVITY_COLUMNS: [&str; ACTIVITY_COLUMNS.len()] = {
+    let mut names = [""; ACTIVITY_COLUMNS.len()];
+    let mut i = 0;
+    while i < names.len() {
+        names[i] = ACTIVITY_COLUMNS[i].name;
+        i += 1;
+    }
+    names
+};
+
+// rows are numbered across sessions, in the order the activities appear
+fn activity_rows(response: &UserActivityResponse) -> Result<Vec<Vec<Cell>>, FlattenError> {
+    response
+        .sessions
+        .iter()
+        .flat_map(|session| {
+            session
+                .activities
+                .iter()
+                .map(move |activity| (session, activity))
+        })
+        .enumerate()
+        .map(|(row_index, (session, activity))| {
+            ACTIVITY_COLUMNS
+                .iter()
+                .map(|column| {
+                    (column.cell)(session, activity).map_err(|value| {
+                        FlattenError::InvalidMetricValue {
+                            location: Location {
+                                report: 0,
+                                row: row_index,
+                                column: Some(column.name.to_string()),
+                            },
+                            value,
+                        }
+                    })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// One object per activity, keyed by `USER_ACTIVITY_COLUMNS`, from the table
+/// `user_activity_to_flat_table` makes of the response.
+pub fn user_activity_to_row_array(response: &UserActivityResponse) -> Result<Value, FlattenError> {
+    let table = user_activity_to_flat_table(response)?;
+
+    table_to_row_array(&table, 0, &FlatJsonOptions::default())
+}
+
+// the numbers are typed like report metrics, every other column is text
+fn activity_column(column: &ActivityColumn) -> Column {
+    Column {
+        name: column.name.to_string(),
+        kind: if column.metric_type.is_some() {
+            ColumnKind::Metric
+        } else {
+            ColumnKind::Dimension
+        },
+        metric_type: column.metric_type.clone(),
+        date_range: None,
+    }
+}
+
+/// A table with a row per activity and `USER_ACTIVITY_COLUMNS` as its columns. Activities
+/// have no summaries or date ranges; the metadata has the response's `totalRows` and
+/// `nextPageToken`, and counts it as sampled when its `sampleRate` is below 1.
+pub fn user_activity_to_flat_table(
+    response: &UserActivityResponse,
+) -> Result<FlatTable, FlattenError> {
+    Ok(FlatTable {
+        columns: ACTIVITY_COLUMNS.iter().map(activity_column).collect(),
+        rows: activity_rows(response)?,
+        totals: None,
+        minimums: None,
+        maximums: None,
+        metadata: ReportMetadata {
+            row_count: response.total_rows,
+            is_data_golden: None,
+            is_sampled: response.sample_rate.is_some_and(|rate| rate < 1.0),
+            samples_read_counts: None,
+            sampling_space_sizes: None,
+            next_page_token: response.next_page_token.clone(),
+        },
+        layout: Layout::Wide,
+        number_of_date_ranges: 0,
+    })
+}
+
+/// One line per activity, with a header of `USER_ACTIVITY_COLUMNS` when `include_header`
+/// is set, written like a report from `user_activity_to_flat_table`. Options about
+/// summary rows, date ranges and the layout don't apply.
+pub fn write_user_activity<W: Write>(
+    response: &UserActivityResponse,
+    writer: &mut W,
+    options: &DelimitedOptions,
+) -> Result<(), FlattenError> {
+    check_options(options)?;
+
+    let table = user_activity_to_flat_table(response)?;
+
+    write_table(&table, 0, writer, &mut OutputPosition::default(), options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    fn read_response() -> UserActivityResponse {
+        let data: String = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_reports/user_activity.json"),
+        )
+        .unwrap();
+
+        serde_json::from_str(data.as_str()).unwrap()
+    }
+
+    #[test]
+    fn one_row_per_activity() {
+        let rows = user_activity_to_row_array(&read_response()).unwrap();
+
+        assert_eq!(rows.as_array().unwrap().len(), 4);
+        let mut keys: Vec<&str> = rows[0]
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        let mut columns = USER_ACTIVITY_COLUMNS.to_vec();
+        keys.sort_unstable();
+        columns.sort_unstable();
+        assert_eq!(keys, columns);
+        assert_eq!(rows[0]["sessionId"], json!("1234567890"));
+        assert_eq!(rows[0]["pagePath"], json!("/"));
+        assert_eq!(rows[0]["eventValue"], Value::Null);
+        assert_eq!(rows[1]["deviceCategory"], json!("desktop"));
+        assert_eq!(rows[1]["eventCategory"], json!("video"));
+        assert_eq!(rows[1]["eventValue"], json!(30));
+        assert_eq!(rows[2]["goalNames"], json!("Signup, Newsletter"));
+        assert_eq!(rows[2]["goalCompletions"], json!(2));
+        assert_eq!(rows[2]["goalValue"], json!(15.5));
+        assert_eq!(rows[3]["sessionId"], json!("1234567891"));
+        assert_eq!(rows[3]["platform"], json!("Android"));
+        assert_eq!(rows[3]["transactionRevenue"], json!(42.99));
+        assert_eq!(rows[3]["productSkus"], json!("SKU-1, SKU-2"));
+    }
+
+    #[test]
+    fn delimited() {
+        let mut output: Vec<u8> = Vec::new();
+        let options = DelimitedOptions {
+            quote_style: crate::to_delimited::QuoteStyle::Necessary,
+            ..Default::default()
+        };
+        write_user_activity(&read_response(), &mut output, &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], USER_ACTIVITY_COLUMNS.join(","));
+        assert_eq!(
+            lines[2],
+            "1234567890,desktop,Windows,WEB,2024-01-15,2024-01-15T10:02:10.000Z,EVENT,google,organic,Organic Search,(not set),(not provided),www.example.com,/,,,,,video,play,intro,30,1,,,,,,,"
+        );
+    }
+
+    #[test]
+    fn flat_table_with_typed_columns() {
+        let table = user_activity_to_flat_table(&read_response()).unwrap();
+
+        assert_eq!(table.columns.len(), USER_ACTIVITY_COLUMNS.len());
+        assert_eq!(table.columns[0].kind, ColumnKind::Dimension);
+        assert_eq!(table.columns[21].name, "eventValue");
+        assert_eq!(table.columns[21].kind, ColumnKind::Metric);
+        assert_eq!(table.columns[21].metric_type, Some(MetricType::Integer));
+        assert_eq!(table.rows.len(), 4);
+        assert_eq!(table.rows[0][21], Cell::Empty);
+        assert_eq!(
+            table.rows[1][21],
+            Cell::Metric {
+                text: "30".to_string(),
+                number: Number::from(30),
+            }
+        );
+        assert_eq!(table.metadata.row_count, Some(2));
+        assert!(!table.metadata.is_sampled);
+    }
+
+    #[test]
+    fn delimited_with_metadata_comments() {
+        let mut output: Vec<u8> = Vec::new();
+        let options = DelimitedOptions {
+            include_metadata_comments: true,
+            ..Default::default()
+        };
+        write_user_activity(&read_response(), &mut output, &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.ends_with("\n# isSampled: false\n# rowCount: 2\n"));
+    }
+
+    #[test]
+    fn rejects_invalid_integers() {
+        let response: UserActivityResponse = serde_json::from_str(
+            r#"{"sessions": [{"activities": [{"event": {"eventValue": "lots"}}]}]}"#,
+        )
+        .unwrap();
+
+        match user_activity_to_row_array(&response) {
+            Err(FlattenError::InvalidMetricValue { location, value }) => {
+                assert_eq!(location.column, Some("eventValue".to_string()));
+                assert_eq!(value, "lots");
+            }
+            other => panic!("expected an invalid metric value, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_goal_completions_overflowing_the_total() {
+        let response: UserActivityResponse = serde_json::from_str(
+            r#"{"sessions": [{"activities": [{"goals": {"goals": [
+                {"goalCompletions": "9223372036854775807"},
+                {"goalCompletions": "1"}
+            ]}}]}]}"#,
+        )
+        .unwrap();
+
+        match user_activity_to_row_array(&response) {
+            Err(FlattenError::InvalidMetricValue { location, value }) => {
+                assert_eq!(location.column, Some("goalCompletions".to_string()));
+                assert_eq!(value, "1");
+            }
+            other => panic!("expected an invalid metric value, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use crate::error::{FlattenError, Location};
+use crate::types::*;
+use std::collections::HashMap;
+
+/// The response of the Core Reporting API v3 `data.ga.get`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct V3Response {
+    pub column_headers: Vec<V3ColumnHeader>,
+    #[serde(default)]
+    pub rows: Vec<Vec<String>>,
+    #[serde(default)]
+    pub totals_for_all_results: HashMap<String, String>,
+    pub total_results: Option<u32>,
+    pub contains_sampled_data: Option<bool>,
+    pub sample_size: Option<String>,
+    pub sample_space: Option<String>,
+    pub next_link: Option<String>,
+    pub kind: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct V3ColumnHeader {
+    pub name: String,
+    pub column_type: V3ColumnType,
+    #[serde(default)]
+    pub data_type: V3DataType,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum V3ColumnType {
+    Dimension,
+    Metric,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum V3DataType {
+    #[default]
+    String,
+    Integer,
+    Float,
+    Currency,
+    Percent,
+    Time,
+    #[serde(other)]
+    Other,
+}
+
+impl V3DataType {
+    pub fn to_metric_type(self) -> MetricType {
+        match self {
+            V3DataType::Integer => MetricType::Integer,
+            V3DataType::Float => MetricType::Float,
+            V3DataType::Currency => MetricType::Currency,
+            V3DataType::Percent => MetricType::Percent,
+            V3DataType::Time => MetricType::Time,
+            V3DataType::String | V3DataType::Other => MetricType::MetricTypeUnspecified,
+        }
+    }
+}
+
+impl V3Response {
+    fn column_indices(&self, column_type: V3ColumnType) -> Vec<usize> {
+        self.column_headers
+            .iter()
+            .enumerate()
+            .filter(|(_, header)| header.column_type == column_type)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// The response as a v4 report with a single date range, so it can go through the same
+    /// flatteners. Dimension and metric columns may be interleaved in a v3 response, they are
+    /// separated keeping their relative order.
+    pub fn to_report(&self) -> Result<Report, FlattenError> {
+        let dimension_indices = self.column_indices(V3ColumnType::Dimension);
+        let metric_indices = self.column_indices(V3ColumnType::Metric);
+
+        let rows = self
+            .rows
+            .iter()
+            .enumerate()
+            .map(|(row_index, row)| {
+                if row.len() != self.column_headers.len() {
+                    return Err(FlattenError::ShapeMismatch {
+                        location: Location {
+                            report: 0,
+                            row: row_index,
+                            column: self
+                                .column_headers
+                                .get(row.len())
+                                .map(|header| header.name.clone()),
+                        },
+                        expected: self.column_headers.len(),
+                        found: row.len(),
+                    });
+                }
+
+                Ok(ReportRow {
+                    dimensions: dimension_indices.iter().map(|&i| row[i].clone()).collect(),
+                    metrics: vec![DateRangeValue {
+                        values: metric_indices.iter().map(|&i| row[i].clone()).collect(),
+                        pivot_value_regions: vec![],
+                    }],
+                })
+            })
+            .collect::<Result<Vec<ReportRow>, FlattenError>>()?;
+
+        let metric_header_entries: Vec<MetricHeaderEntry> = metric_indices
+            .iter()
+            .map(|&i| MetricHeaderEntry {
+                name: self.column_headers[i].name.clone(),
+                metric_type: self.column_headers[i].data_type.to_metric_type(),
+            })
+            .collect();
+
+        // totals are keyed by metric name, and only usable when every metric has one
+        let totals = metric_header_entries
+            .iter()
+            .map(|entry| self.totals_for_all_results.get(&entry.name).cloned())
+            .collect::<Option<Vec<String>>>()
+            .map(|values| {
+                vec![DateRangeValue {
+                    values,
+                    pivot_value_regions: vec![],
+                }]
+            })
+            .unwrap_or_default();
+
+        let is_sampled = self.contains_sampled_data == Some(true);
+
+        Ok(Report {
+            column_header: ColumnHeader {
+                dimensions: dimension_indices
+                    .iter()
+                    .map(|&i| self.column_headers[i].name.clone())
+                    .collect(),
+                metric_header: MetricHeader {
+                    metric_header_entries,
+                    pivot_headers: vec![],
+                },
+            },
+            data: ReportData {
+                rows,
+                totals,
+                row_count: self.total_results,
+                minimums: None,
+                maximums: None,
+                samples_read_counts: self
+                    .sample_size
+                    .clone()
+                    .filter(|_| is_sampled)
+                    .map(|size| vec![size]),
+                sampling_space_sizes: self
+                    .sample_space
+                    .clone()
+                    .filter(|_| is_sampled)
+                    .map(|space| vec![space]),
+                is_data_golden: None,
+            },
+            next_page_token: None,
+            // v3 and MCF responses give metrics they have no value for as empty strings
+            source: ReportSource {
+                empty_metrics_are_missing: true,
+                ..ReportSource::default()
+            },
+        })
+    }
+}
+
+/// Reads a `data.ga.get` response as a `ReportResponse` with a single report.
+pub fn parse_v3_response(raw_response: &str) -> Result<ReportResponse, FlattenError> {
+    let response: V3Response = serde_json::from_str(raw_response)?;
+
+    Ok(ReportResponse {
+        reports: vec![response.to_report()?],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    fn read_response(name: &str) -> String {
+        fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("test_reports")
+                .join(name),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn separates_dimensions_and_metrics() {
+        let response = parse_v3_response(&read_response("v3_data_ga.json")).unwrap();
+
+        let report = &response.reports[0];
+        assert_eq!(
+            report.column_header.dimensions,
+            vec!["ga:deviceCategory", "ga:country"]
+        );
+        assert_eq!(
+            report.get_metric_headers(),
+            vec![
+                MetricHeaderEntry {
+                    name: "ga:sessions".to_string(),
+                    metric_type: MetricType::Integer,
+                },
+                MetricHeaderEntry {
+                    name: "ga:bounceRate".to_string(),
+                    metric_type: MetricType::Percent,
+                },
+            ]
+        );
+        assert_eq!(report.data.rows[0].dimensions, vec!["desktop", "Canada"]);
+        assert_eq!(report.data.rows[0].metrics[0].values, vec!["43", "51.16"]);
+        assert_eq!(report.data.totals[0].values, vec!["50", "52.0"]);
+        assert_eq!(
+            report.metadata(),
+            ReportMetadata {
+                row_count: Some(3),
+                is_data_golden: None,
+                is_sampled: true,
+                samples_read_counts: Some(vec!["499630".to_string()]),
+                sampling_space_sizes: Some(vec!["15328013".to_string()]),
+                next_page_token: None,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_short_rows() {
+        let raw_response = r#"{
+            "columnHeaders": [
+                {"name": "ga:country", "columnType": "DIMENSION", "dataType": "STRING"},
+                {"name": "ga:sessions", "columnType": "METRIC", "dataType": "INTEGER"}
+            ],
+            "rows": [["Canada", "4"], ["Mexico"]]
+        }"#;
+
+        match parse_v3_response(raw_response) {
+            Err(FlattenError::ShapeMismatch {
+                location,
+                expected: 2,
+                found: 1,
+            }) => assert_eq!(
+                location,
+                Location {
+                    report: 0,
+                    row: 1,
+                    column: Some("ga:sessions".to_string()),
+                }
+            ),
+            other => panic!("expected a shape mismatch, got {:?}", other),
+        }
+    }
+}
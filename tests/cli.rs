@@ -0,0 +1,172 @@
+use assert_cmd::Command;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("test_reports")
+        .join(name)
+}
+
+fn flattener() -> Command {
+    Command::cargo_bin("ga-v4-flattener").unwrap()
+}
+
+fn stdout_of(args: &[&str], input: &str) -> String {
+    let output = flattener()
+        .args(args)
+        .arg(fixture(input))
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn writes_csv_to_stdout() {
+    flattener()
+        .arg(fixture("single_dimension_and_metric.json"))
+        .assert()
+        .success()
+        .stdout("\"ga:deviceCategory\",\"ga:sessions\"\n\"desktop\",43\n\"mobile\",1\n")
+        .stderr("");
+}
+
+#[test]
+fn reads_stdin() {
+    let data = fs::read_to_string(fixture("single_dimension_and_metric.json")).unwrap();
+
+    flattener()
+        .args(["--format", "tsv", "--quote-style", "necessary", "-"])
+        .write_stdin(data)
+        .assert()
+        .success()
+        .stdout("ga:deviceCategory\tga:sessions\ndesktop\t43\nmobile\t1\n");
+}
+
+#[test]
+fn writes_a_file_per_report() {
+    let output_dir =
+        std::env::temp_dir().join(format!("ga-v4-flattener-cli-{}", std::process::id()));
+
+    flattener()
+        .args(["--format", "json", "--output-dir"])
+        .arg(&output_dir)
+        .args(["--filename-template", "ga_{index}.{ext}"])
+        .arg(fixture("multiple_reports.json"))
+        .assert()
+        .success()
+        .stdout("");
+
+    assert!(output_dir.join("ga_0.json").is_file());
+    assert!(output_dir.join("ga_1.json").is_file());
+    fs::remove_dir_all(output_dir).unwrap();
+}
+
+#[test]
+fn reads_other_sources() {
+    assert!(stdout_of(&["--source", "ga4"], "ga4_run_report.json")
+        .starts_with("\"country\",\"deviceCategory\",\"activeUsers\""));
+    assert_eq!(
+        stdout_of(
+            &[
+                "--source",
+                "ga4-pivot",
+                "--pivot-layout",
+                "long",
+                "--format",
+                "jsonl"
+            ],
+            "ga4_pivot_report.json"
+        )
+        .lines()
+        .next(),
+        Some(
+            "{\"browser\":\"Chrome\",\"country\":\"United States\",\"engagementRate\":0.64,\
+             \"report_index\":0,\"sessions\":812}"
+        )
+    );
+    assert!(
+        stdout_of(&["--source", "v3", "--format", "json"], "v3_data_ga.json")
+            .starts_with("[[{\"ga:bounceRate\":51.16,")
+    );
+    assert!(
+        stdout_of(&["--source", "mcf", "--paths", "exploded"], "mcf_data.json")
+            .contains("\n\"Direct\",1,12,840.5\n\"Organic Search\",2,12,840.5\n")
+    );
+    assert_eq!(
+        stdout_of(&["--source", "user-activity"], "user_activity.json")
+            .lines()
+            .count(),
+        5
+    );
+}
+
+#[test]
+fn compares_date_ranges() {
+    let output = stdout_of(
+        &["--compare", "0", "--quote-style", "necessary"],
+        "multiple_date_ranges.json",
+    );
+
+    assert!(output.starts_with(
+        "ga:browser,ga:avgTimeOnPage,ga:pageviewsPerSession,ga:avgTimeOnPage_2,\
+         ga:pageviewsPerSession_2,ga:avgTimeOnPage_2_delta,"
+    ));
+}
+
+#[test]
+fn fails_on_a_missing_file() {
+    flattener()
+        .arg("no_such_report.json")
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(
+            "error: failed to open no_such_report.json: No such file or directory (os error 2)\n",
+        );
+}
+
+#[test]
+fn fails_on_an_unsupported_feature() {
+    flattener()
+        .arg("--totals")
+        .arg(fixture("no_dimensions.json"))
+        .assert()
+        .code(1)
+        .stderr(
+            "error: unsupported: report 0 has no dimensions to label its TOTAL, MIN and MAX \
+             rows with\n",
+        );
+}
+
+#[test]
+fn fails_on_invalid_arguments() {
+    for (args, message) in [
+        (&["--bogus"][..], "error: unknown option --bogus\n"),
+        (&["--format", "xml"][..], "error: unknown format \"xml\"\n"),
+        (
+            &["first.json", "second.json"][..],
+            "error: unexpected argument second.json, only one INPUT can be given\n",
+        ),
+        (
+            &["--format", "jsonl", "--output-dir", "out", "--totals"][..],
+            "error: --totals, --minimums, --maximums and --metadata can't be used with \
+             --format jsonl\n",
+        ),
+        (
+            &["--metadata", "--format", "jsonl"][..],
+            "error: --totals, --minimums, --maximums and --metadata can't be used with \
+             --format jsonl\n",
+        ),
+    ] {
+        let output = flattener().args(args).assert().code(2).get_output().clone();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+
+        assert!(stderr.starts_with(message), "{}", stderr);
+        assert!(stderr.contains("Usage: ga-v4-flattener [OPTIONS] [INPUT]"));
+    }
+}